@@ -35,17 +35,26 @@ complete -c paper -n "__fish_paper_needs_command" -f -a "save" -d 'Make a git co
 complete -c paper -n "__fish_paper_needs_command" -f -a "push" -d 'Push local git changes to the remote repository, creating one if necessary.'
 complete -c paper -n "__fish_paper_needs_command" -f -a "web" -d 'Open the remote repository’s GitHub site.'
 complete -c paper -n "__fish_paper_needs_command" -f -a "wc" -d 'Print word count metrics for the project, stripping out metadata, citations, and footnotes.'
-complete -c paper -n "__fish_paper_needs_command" -f -a "watch" -d 'Watches the content directory and emits new wordcount data on each change, optionally rebuilding.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "watch" -d 'Watches the content directory, resources, and bibliographies and emits new wordcount data on each change, optionally rebuilding.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "meta" -d 'Work with the project’s `paper_meta.yml`.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "refs" -d 'Manage the project’s bibliography sources.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "resources" -d 'Manage the project’s copy of paper’s templates, filters, and styles.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "import-comments" -d 'Pull the comments out of a returned Word document and match them up with the content files.'
+complete -c paper -n "__fish_paper_needs_command" -f -a "doctor" -d 'Check that everything paper needs is installed and working.'
 complete -c paper -n "__fish_paper_needs_command" -f -a "fmt" -d 'Run an automated formatter on all the local Markdown files.'
 complete -c paper -n "__fish_paper_needs_command" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand new" -l from -d 'An existing .docx or .tex document to convert into the project’s content.' -r
 complete -c paper -n "__fish_paper_using_subcommand new" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand new" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand init" -l from -d 'An existing .docx or .tex document to convert into the project’s content.' -r
 complete -c paper -n "__fish_paper_using_subcommand init" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand init" -s h -l help -d 'Print help'
 complete -c paper -n "__fish_paper_using_subcommand dev" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand dev" -s h -l help -d 'Print help'
-complete -c paper -n "__fish_paper_using_subcommand build" -s t -l output-format -d 'The desired format of the output file' -r -f -a "{docx\t'',latex\t'',latex+pdf\t'',json\t''}"
+complete -c paper -n "__fish_paper_using_subcommand build" -s t -l output-format -d 'The desired format of the output file' -r -f -a "{docx\t'',latex\t'',latex+pdf\t'',html\t'',json\t''}"
 complete -c paper -n "__fish_paper_using_subcommand build" -l docx-revision -d 'Revision number for docx output format; if unset or negative, will use the number of times the project was saved.' -r
+complete -c paper -n "__fish_paper_using_subcommand build" -l verify-reproducible -d 'Build twice and make sure the output is byte-for-byte identical.'
+complete -c paper -n "__fish_paper_using_subcommand build" -l strip-annotations -d 'Leave out review comments and tracked changes (accepting insertions and dropping deletions) for a clean submission copy.'
 complete -c paper -n "__fish_paper_using_subcommand build" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand build" -s h -l help -d 'Print help'
 complete -c paper -n "__fish_paper_using_subcommand save" -l message -d 'A memo describing this version of the paper (used in the git commit message)' -r
@@ -58,24 +67,65 @@ complete -c paper -n "__fish_paper_using_subcommand web" -s h -l help -d 'Print
 complete -c paper -n "__fish_paper_using_subcommand wc" -l full -d 'Show full pre-stripped word count of each file as well.'
 complete -c paper -n "__fish_paper_using_subcommand wc" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand wc" -s h -l help -d 'Print help'
-complete -c paper -n "__fish_paper_using_subcommand watch" -s t -l output-format -d 'The desired format of the output file' -r -f -a "{docx\t'',latex\t'',latex+pdf\t'',json\t''}"
+complete -c paper -n "__fish_paper_using_subcommand watch" -l debounce -d 'How many milliseconds to wait for changes to settle before responding.' -r
+complete -c paper -n "__fish_paper_using_subcommand watch" -l port -d 'Port for the preview server (with `--serve`).' -r
+complete -c paper -n "__fish_paper_using_subcommand watch" -s t -l output-format -d 'The desired format of the output file' -r -f -a "{docx\t'',latex\t'',latex+pdf\t'',html\t'',json\t''}"
 complete -c paper -n "__fish_paper_using_subcommand watch" -l docx-revision -d 'Revision number for docx output format; if unset or negative, will use the number of times the project was saved.' -r
 complete -c paper -n "__fish_paper_using_subcommand watch" -l full -d 'Show full pre-stripped word count of each file as well.'
 complete -c paper -n "__fish_paper_using_subcommand watch" -l build -d 'Rebuild the project before showing word count'
+complete -c paper -n "__fish_paper_using_subcommand watch" -l serve -d 'Serve a live-reloading HTML preview of the paper on localhost'
 complete -c paper -n "__fish_paper_using_subcommand watch" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand watch" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand meta; and not __fish_seen_subcommand_from check help" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand meta; and not __fish_seen_subcommand_from check help" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand meta; and not __fish_seen_subcommand_from check help" -f -a "check" -d 'Check `paper_meta.yml` for unknown keys and invalid values.'
+complete -c paper -n "__fish_paper_using_subcommand meta; and not __fish_seen_subcommand_from check help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand meta; and __fish_seen_subcommand_from check" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand meta; and __fish_seen_subcommand_from check" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand meta; and __fish_seen_subcommand_from help" -f -a "check" -d 'Check `paper_meta.yml` for unknown keys and invalid values.'
+complete -c paper -n "__fish_paper_using_subcommand meta; and __fish_seen_subcommand_from help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand refs; and not __fish_seen_subcommand_from sync help" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand refs; and not __fish_seen_subcommand_from sync help" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand refs; and not __fish_seen_subcommand_from sync help" -f -a "sync" -d 'Pull references from the `refs.sync_from` Zotero export path or local HTTP endpoint into the project bibliography.'
+complete -c paper -n "__fish_paper_using_subcommand refs; and not __fish_seen_subcommand_from sync help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand refs; and __fish_seen_subcommand_from sync" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand refs; and __fish_seen_subcommand_from sync" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand refs; and __fish_seen_subcommand_from help" -f -a "sync" -d 'Pull references from the `refs.sync_from` Zotero export path or local HTTP endpoint into the project bibliography.'
+complete -c paper -n "__fish_paper_using_subcommand refs; and __fish_seen_subcommand_from help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand resources; and not __fish_seen_subcommand_from update help" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand resources; and not __fish_seen_subcommand_from update help" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand resources; and not __fish_seen_subcommand_from update help" -f -a "update" -d 'Bring `.paper_resources` up to date with the installed version of paper, leaving locally modified files alone.'
+complete -c paper -n "__fish_paper_using_subcommand resources; and not __fish_seen_subcommand_from update help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand resources; and __fish_seen_subcommand_from update" -l force -d 'Replace locally modified files too.'
+complete -c paper -n "__fish_paper_using_subcommand resources; and __fish_seen_subcommand_from update" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand resources; and __fish_seen_subcommand_from update" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand resources; and __fish_seen_subcommand_from help" -f -a "update" -d 'Bring `.paper_resources` up to date with the installed version of paper, leaving locally modified files alone.'
+complete -c paper -n "__fish_paper_using_subcommand resources; and __fish_seen_subcommand_from help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand import-comments" -l inline -d 'Put the comments into the content files as annotations instead of writing a report to the research directory.'
+complete -c paper -n "__fish_paper_using_subcommand import-comments" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand import-comments" -s h -l help -d 'Print help'
+complete -c paper -n "__fish_paper_using_subcommand doctor" -s v -l verbose -d 'Spam the output log'
+complete -c paper -n "__fish_paper_using_subcommand doctor" -s h -l help -d 'Print help'
 complete -c paper -n "__fish_paper_using_subcommand fmt" -l columns -d 'The number of characters that can be in each line before wrapping.' -r
 complete -c paper -n "__fish_paper_using_subcommand fmt" -l no-wrap -d 'Do not add linebreaks to wrap the Markdown text.'
 complete -c paper -n "__fish_paper_using_subcommand fmt" -s v -l verbose -d 'Spam the output log'
 complete -c paper -n "__fish_paper_using_subcommand fmt" -s h -l help -d 'Print help'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "new" -d 'Create a new directory with the scaffolding for a new writing/research project.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "init" -d 'While in an empty directory, set it up for a project. (Called as part of the process for `new`.)'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "dev" -d 'Set up a project for development work on paper itself. Deletes the local `.paper_resources` directory and symlinks the template’s version, so changes here affect the actual program.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "build" -d 'Generate versions of the paper ready for submission.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "save" -d 'Make a git commit with some extra tracking data.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "push" -d 'Push local git changes to the remote repository, creating one if necessary.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "web" -d 'Open the remote repository’s GitHub site.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "wc" -d 'Print word count metrics for the project, stripping out metadata, citations, and footnotes.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "watch" -d 'Watches the content directory and emits new wordcount data on each change, optionally rebuilding.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "fmt" -d 'Run an automated formatter on all the local Markdown files.'
-complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch fmt help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "new" -d 'Create a new directory with the scaffolding for a new writing/research project.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "init" -d 'While in an empty directory, set it up for a project. (Called as part of the process for `new`.)'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "dev" -d 'Set up a project for development work on paper itself. Deletes the local `.paper_resources` directory and symlinks the template’s version, so changes here affect the actual program.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "build" -d 'Generate versions of the paper ready for submission.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "save" -d 'Make a git commit with some extra tracking data.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "push" -d 'Push local git changes to the remote repository, creating one if necessary.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "web" -d 'Open the remote repository’s GitHub site.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "wc" -d 'Print word count metrics for the project, stripping out metadata, citations, and footnotes.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "watch" -d 'Watches the content directory, resources, and bibliographies and emits new wordcount data on each change, optionally rebuilding.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "meta" -d 'Work with the project’s `paper_meta.yml`.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "refs" -d 'Manage the project’s bibliography sources.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "resources" -d 'Manage the project’s copy of paper’s templates, filters, and styles.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "import-comments" -d 'Pull the comments out of a returned Word document and match them up with the content files.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "doctor" -d 'Check that everything paper needs is installed and working.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "fmt" -d 'Run an automated formatter on all the local Markdown files.'
+complete -c paper -n "__fish_paper_using_subcommand help; and not __fish_seen_subcommand_from new init dev build save push web wc watch meta refs resources import-comments doctor fmt help" -f -a "help" -d 'Print this message or the help of the given subcommand(s)'
+complete -c paper -n "__fish_paper_using_subcommand help; and __fish_seen_subcommand_from meta" -f -a "check" -d 'Check `paper_meta.yml` for unknown keys and invalid values.'
+complete -c paper -n "__fish_paper_using_subcommand help; and __fish_seen_subcommand_from refs" -f -a "sync" -d 'Pull references from the `refs.sync_from` Zotero export path or local HTTP endpoint into the project bibliography.'
+complete -c paper -n "__fish_paper_using_subcommand help; and __fish_seen_subcommand_from resources" -f -a "update" -d 'Bring `.paper_resources` up to date with the installed version of paper, leaving locally modified files alone.'
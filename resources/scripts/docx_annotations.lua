@@ -0,0 +1,56 @@
+-- Turns review annotations into markers that the docx post-processing swaps out
+--    for real Word comments and tracked changes:
+--      [commented text]{.comment author="Jane Doe" note="Is this right?"}
+--      [added text]{.ins author="Jane Doe"}
+--      [removed text]{.del author="Jane Doe"}
+
+local ANNOTATION_NS = "https://github.com/sjml/paper/annotations"
+local KINDS = { "comment", "ins", "del" }
+local next_id = 0
+
+local function escape_attr(s)
+  s = s:gsub("&", "&amp;")
+  s = s:gsub("<", "&lt;")
+  s = s:gsub(">", "&gt;")
+  s = s:gsub('"', "&quot;")
+  s = s:gsub("\n", "&#10;")
+  return s
+end
+
+local function marker(tag, attrs)
+  local parts = { "<paper:" .. tag .. ' xmlns:paper="' .. ANNOTATION_NS .. '"' }
+  for _, kv in ipairs(attrs) do
+    table.insert(parts, " " .. kv[1] .. '="' .. escape_attr(kv[2]) .. '"')
+  end
+  table.insert(parts, "/>")
+  return pandoc.RawInline("openxml", table.concat(parts))
+end
+
+function Span(s)
+  local kind = nil
+  for _, k in ipairs(KINDS) do
+    if s.classes:includes(k) then
+      kind = k
+      break
+    end
+  end
+  if kind == nil then
+    return nil
+  end
+
+  next_id = next_id + 1
+  local id = tostring(next_id)
+  local attrs = { { "kind", kind }, { "id", id } }
+  for _, key in ipairs({ "author", "note", "date" }) do
+    if s.attributes[key] ~= nil then
+      table.insert(attrs, { key, s.attributes[key] })
+    end
+  end
+
+  local out = { marker("start", attrs) }
+  for _, inline in ipairs(s.content) do
+    table.insert(out, inline)
+  end
+  table.insert(out, marker("end", { { "id", id } }))
+  return out
+end
@@ -16,3 +16,10 @@ end
 function Header(h)
   return {}
 end
+
+-- tracked deletions from review annotations aren't part of the text anymore
+function Span(s)
+  if s.classes:includes("del") then
+    return {}
+  end
+end
@@ -0,0 +1,42 @@
+#!/usr/bin/env bash
+
+# run before tagging a release, so later versions can recognize this one's
+#   resources in projects that haven't touched them; with `--check`, just
+#   makes sure what's recorded for this version matches the resources as
+#   they are now
+
+set -e
+
+cd "$(dirname "$0")"
+cd ..
+
+CARGO_VERSION=$(awk -F ' = ' '$1 == "version" { gsub(/[\"]/, "", $2); printf("%s",$2) }' Cargo.toml)
+HASHES_FILE=resources/release_resource_hashes.txt
+
+current_hashes() {
+  (
+    cd resources/project_template/.paper_resources
+    find . -type f | sed 's|^\./||' | LC_ALL=C sort | while read -r f; do
+      echo "$CARGO_VERSION $(shasum -a 256 "$f" | cut -d ' ' -f 1) $f"
+    done
+  )
+}
+
+if [[ "$1" == "--check" ]]; then
+  if ! grep -q "^$CARGO_VERSION " $HASHES_FILE; then
+    echo "ERROR: no resource hashes for $CARGO_VERSION; run scripts/record_resource_hashes.sh first!"
+    exit 1
+  fi
+  if ! diff <(grep "^$CARGO_VERSION " $HASHES_FILE) <(current_hashes); then
+    echo "ERROR: the resources have changed since $CARGO_VERSION's hashes were recorded; bump the version and record them again!"
+    exit 1
+  fi
+  exit 0
+fi
+
+if grep -q "^$CARGO_VERSION " $HASHES_FILE; then
+  echo "ERROR: resource hashes for $CARGO_VERSION are already recorded!"
+  exit 1
+fi
+
+current_hashes >> $HASHES_FILE
@@ -11,7 +11,7 @@ if [[ ${#PORCELAIN} -ne 0 ]]; then
   exit 1
 fi
 
-CARGO_VERSION=$(awk -F ' = ' '$1 ~ /version/ { gsub(/[\"]/, "", $2); printf("%s",$2) }' Cargo.toml)
+CARGO_VERSION=$(awk -F ' = ' '$1 == "version" { gsub(/[\"]/, "", $2); printf("%s",$2) }' Cargo.toml)
 export VERSION_TAG=v$CARGO_VERSION
 
 git fetch --tags origin
@@ -27,6 +27,8 @@ if [ "$newer_tag" != "$CARGO_VERSION" ]; then
   exit 1
 fi
 
+./scripts/record_resource_hashes.sh --check
+
 echo "Tagging $VERSION_TAG..."
 git tag $VERSION_TAG main --message "tagging ${VERSION_TAG%%*( )}"
 
@@ -1,5 +1,6 @@
+use std::collections::BTreeMap;
 use std::fs;
-use std::io::Write;
+use std::io::{Read, Write};
 use std::path;
 use std::str::FromStr;
 use std::time::UNIX_EPOCH;
@@ -7,16 +8,20 @@ use std::time::UNIX_EPOCH;
 use anyhow::{bail, Context, Result};
 use regex::Regex;
 use serde_json::{self, Value};
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
 
 use crate::config::CONFIG;
 use crate::docx;
 use crate::formats::{self, OutputFormat};
+use crate::html;
 use crate::latex;
 use crate::metadata::PaperMeta;
 use crate::pandoc_wrap;
+use crate::refs;
 use crate::subprocess;
 use crate::util;
+use crate::wc;
 
 pub fn get_content_file_list() -> Vec<String> {
     let mut content_files = WalkDir::new(&CONFIG.get().content_directory_name)
@@ -87,17 +92,18 @@ fn generate_filename(meta: &PaperMeta) -> Result<String> {
     let whitespace_search = Regex::new(r"\s").context("Could not compile regex")?;
 
     // pull the first (or only) author's last name
-    let author_splits = meta
-        .get_string(&["data", "author"])
-        .expect("No author in metadata.");
-    let authors: Vec<&str> = author_splits.split(',').map(|s| s.trim()).collect();
-    let author_label = authors.first().unwrap().split(' ').last().unwrap();
-    match meta.get_string(&["data", "class_mnemonic"]) {
+    let author_label = meta
+        .data
+        .authors
+        .first()
+        .and_then(|a| a.get_family_name())
+        .context("No author in metadata.")?;
+    match &meta.data.class_mnemonic {
         Some(mnemonic) => {
             filename = format!(
                 "{}_{}",
                 author_label,
-                whitespace_search.replace(&mnemonic, "")
+                whitespace_search.replace(mnemonic, "")
             );
         }
         None => {
@@ -112,22 +118,77 @@ fn generate_filename(meta: &PaperMeta) -> Result<String> {
     Ok(filename)
 }
 
+pub fn get_output_filename(meta: &PaperMeta) -> Result<String> {
+    match &meta.filename {
+        Some(fname) => Ok(fname.clone()),
+        None => generate_filename(meta),
+    }
+}
+
+// variables from the `title_page` settings that both the docx and LaTeX
+//   title pages can show; these win over any `data` keys of the same name
+pub fn get_title_page_variables(meta: &PaperMeta) -> Result<Vec<(String, String)>> {
+    let mut vars = vec![];
+    if meta.title_page.show_word_count {
+        // same stripped count that `paper wc` shows
+        let total: usize = wc::wc_data()?.iter().map(|d| d.2).sum();
+        vars.push(("word_count".to_string(), total.to_string()));
+    }
+    if let Some(pledge) = &meta.title_page.pledge {
+        vars.push(("pledge".to_string(), pledge.clone()));
+    }
+    Ok(vars)
+}
+
+// the format from the command line if it was given, otherwise the
+//   meta file's `default_format` (if any)
+pub fn resolve_output_format(
+    meta: &PaperMeta,
+    output_format: &formats::OutputFormat,
+    of_specified: bool,
+) -> Result<formats::OutputFormat> {
+    if !of_specified {
+        if let Some(df) = &meta.default_format {
+            return Ok(formats::OutputFormat::from_str(df)?);
+        }
+    }
+    Ok(output_format.clone())
+}
+
+fn get_builder(of: &formats::OutputFormat) -> Box<dyn formats::Builder> {
+    match of {
+        OutputFormat::Docx => Box::<docx::DocxBuilder>::default(),
+        OutputFormat::LaTeX => Box::<latex::LatexBuilder>::default(),
+        OutputFormat::LaTeXPdf => Box::<latex::LatexPdfBuilder>::default(),
+        OutputFormat::Html => Box::<html::HtmlBuilder>::default(),
+        _ => {
+            // wrong, just leaving here now until the rest of the arms are filled
+            Box::<latex::LatexBuilder>::default()
+        }
+    }
+}
+
+pub fn get_output_file_path(meta: &PaperMeta, of: &formats::OutputFormat) -> Result<path::PathBuf> {
+    Ok(
+        path::Path::new(&CONFIG.get().output_directory_name).join(format!(
+            "{}.{}",
+            get_output_filename(meta)?,
+            get_builder(of).get_output_file_suffix()
+        )),
+    )
+}
+
 pub fn build(
     output_format: &formats::OutputFormat,
     of_specified: bool,
     docx_revision: i64,
+    strip_annotations: bool,
 ) -> Result<()> {
     util::ensure_paper_dir()?;
 
     let mut meta = PaperMeta::new()?;
 
-    let mut of = output_format.clone();
-    if !of_specified {
-        of = match meta.get_string(&["default_format"]) {
-            Some(df) => formats::OutputFormat::from_str(&df)?,
-            None => of,
-        };
-    }
+    let of = resolve_output_format(&meta, output_format, of_specified)?;
 
     if CONFIG.get().verbose {
         println!("Building for format {:?}.", of);
@@ -156,42 +217,33 @@ pub fn build(
         CONFIG.get().content_directory_name.clone(),
     ];
 
-    let mut builder: Box<dyn formats::Builder>;
-    match of {
-        OutputFormat::Docx => {
-            meta.set_int(&["docx", "revision"], docx_revision)?;
-            builder = Box::<docx::DocxBuilder>::default();
-        }
-        OutputFormat::LaTeX => {
-            builder = Box::<latex::LatexBuilder>::default();
-        }
-        OutputFormat::LaTeXPdf => {
-            builder = Box::<latex::LatexPdfBuilder>::default();
-        }
-        _ => {
-            // wrong, just leaving here now until the rest of the arms are filled
-            builder = Box::<latex::LatexBuilder>::default();
+    if of == OutputFormat::Docx {
+        meta.docx.revision = Some(docx_revision);
+        if strip_annotations {
+            meta.docx.strip_annotations = true;
         }
     }
+    let mut builder = get_builder(&of);
 
     builder.prepare(&mut pandoc_args, &meta)?;
 
-    let filename = match meta.get_string(&["filename"]) {
+    let filename = match meta.filename.clone() {
         Some(fname) => fname,
         None => {
             let generated = generate_filename(&meta)?;
             if CONFIG.get().verbose {
                 println!("No filename given; using generated \"{}\".", generated);
             }
-            meta.set_string(&["filename"], &generated)?;
+            meta.filename = Some(generated.clone());
             generated
         }
     };
     let output_file_path =
         out_path.join(format!("{}.{}", filename, builder.get_output_file_suffix()));
+    let pandoc_output_path = builder.get_pandoc_output_path(&output_file_path)?;
     pandoc_args.push("--output".to_string());
     pandoc_args.push(
-        output_file_path
+        pandoc_output_path
             .as_path()
             .to_str()
             .context("Can't unwrap output file path.")
@@ -216,13 +268,20 @@ pub fn build(
         pandoc_args.push(lf.path().to_string_lossy().to_string());
     }
 
-    if let Some(bib_sources) = meta.get_vec_string(&["sources"]) {
+    let bib_sources = refs::get_bibliography_paths(&meta)?;
+    if !bib_sources.is_empty() {
         if CONFIG.get().verbose {
             println!("Processing citations...");
         }
+        for changed in refs::get_changed_bibliographies(&meta)? {
+            println!(
+                "Bibliography `{}` has changed since the last build.",
+                changed
+            );
+        }
         pandoc_args.push("--citeproc".to_string());
         pandoc_args.push("--csl".to_string());
-        if !(meta.get_bool(&["use_ibid"]).unwrap_or(false)) {
+        if !meta.use_ibid {
             pandoc_args.push(
                 ".paper_resources/chicago-fullnote-bibliography-short-title-subsequent.csl"
                     .to_string(),
@@ -233,18 +292,7 @@ pub fn build(
         }
         for bs in bib_sources {
             pandoc_args.push("--bibliography".to_string());
-
-            let mut source = bs.clone();
-            if source.starts_with('~') {
-                source = source.replacen(
-                    '~',
-                    std::env::var("HOME")
-                        .context("Could not get $HOME env var")?
-                        .as_str(),
-                    1,
-                );
-            }
-            pandoc_args.push(source);
+            pandoc_args.push(bs);
         }
     } else if CONFIG.get().verbose {
         println!("No citation processing.");
@@ -266,7 +314,7 @@ pub fn build(
         pandoc_args.push(lf.path().to_string_lossy().to_string());
     }
 
-    let sentinels = &meta.get_vec_string(&["sentinels"]).unwrap_or(vec![]);
+    let sentinels = &meta.sentinels;
     for content_file in builder.get_file_list() {
         let content = fs::read_to_string(content_file.clone())
             .with_context(|| format!("ERROR: Could not read content file {:?}", content_file))?;
@@ -300,10 +348,103 @@ pub fn build(
     Ok(())
 }
 
+fn hash_file(path: &path::Path) -> Result<String> {
+    let datums = fs::read(path).with_context(|| format!("Could not read {:?}", path))?;
+    Ok(format!("{:x}", Sha256::digest(&datums)))
+}
+
+// hashes of each file inside a zip-based output (like docx), for narrowing
+//   down *what* changed; None if it's not a zip
+fn get_zip_entry_hashes(path: &path::Path) -> Option<BTreeMap<String, String>> {
+    let mut archive = zip::ZipArchive::new(fs::File::open(path).ok()?).ok()?;
+    let mut hashes = BTreeMap::new();
+    for i in 0..archive.len() {
+        let mut entry = archive.by_index(i).ok()?;
+        let mut datums = vec![];
+        entry.read_to_end(&mut datums).ok()?;
+        hashes.insert(
+            entry.name().to_string(),
+            format!("{:x}", Sha256::digest(&datums)),
+        );
+    }
+    Some(hashes)
+}
+
+pub fn verify_reproducible(
+    output_format: &formats::OutputFormat,
+    of_specified: bool,
+    docx_revision: i64,
+    strip_annotations: bool,
+) -> Result<()> {
+    util::ensure_paper_dir()?;
+
+    build(
+        output_format,
+        of_specified,
+        docx_revision,
+        strip_annotations,
+    )?;
+
+    let meta = PaperMeta::new()?;
+    let of = resolve_output_format(&meta, output_format, of_specified)?;
+    let output_file_path = get_output_file_path(&meta, &of)?;
+    let first_hash = hash_file(&output_file_path)?;
+    let first_entries = get_zip_entry_hashes(&output_file_path);
+    if CONFIG.get().verbose {
+        println!("First build: {}", first_hash);
+    }
+
+    build(
+        output_format,
+        of_specified,
+        docx_revision,
+        strip_annotations,
+    )?;
+
+    let second_hash = hash_file(&output_file_path)?;
+    if CONFIG.get().verbose {
+        println!("Second build: {}", second_hash);
+    }
+
+    if first_hash == second_hash {
+        println!(
+            "{} is reproducible (SHA-256 {}).",
+            output_file_path.display(),
+            first_hash
+        );
+        return Ok(());
+    }
+
+    if let (Some(first), Some(second)) = (first_entries, get_zip_entry_hashes(&output_file_path)) {
+        let mut names: Vec<&String> = first.keys().chain(second.keys()).collect();
+        names.sort();
+        names.dedup();
+        let differing: Vec<&String> = names
+            .into_iter()
+            .filter(|n| first.get(*n) != second.get(*n))
+            .collect();
+        if differing.is_empty() {
+            eprintln!("All archive entries match; the difference is in the zip metadata.");
+        } else {
+            eprintln!("Archive entries that differ between builds:");
+            for name in differing {
+                eprintln!("\t{}", name);
+            }
+        }
+    }
+    bail!(
+        "{} is not reproducible: {} vs. {}",
+        output_file_path.display(),
+        first_hash,
+        second_hash
+    );
+}
+
 fn record_build_data(log_lines: &[String], meta: &PaperMeta) -> Result<()> {
     util::stamp_local_dir()?;
 
-    if let Some(bib_paths) = meta.get_vec_string(&["sources"]) {
+    let bib_paths = refs::get_bibliography_paths(meta)?;
+    if !bib_paths.is_empty() {
         let mut cited_refence_keys = vec![];
 
         let lua_path = CONFIG
@@ -325,20 +466,12 @@ fn record_build_data(log_lines: &[String], meta: &PaperMeta) -> Result<()> {
 
         let mut bpp_strings = vec![];
         for bp in bib_paths {
-            let mut bp_local = bp.clone();
-            if bp.starts_with('~') {
-                bp_local = format!(
-                    "{}{}",
-                    std::env::var("HOME").context("No $HOME variable set.")?,
-                    &bp_local[1..]
-                );
-            }
-            bpp_strings.push(bp_local.clone());
-            let bpp = path::Path::new(&bp_local);
+            bpp_strings.push(bp.clone());
+            let bpp = path::Path::new(&bp);
             if !bpp.exists() {
                 bail!("No such file for bibliography source: {}", bp);
             }
-            args.extend_from_slice(&["--bibliography".to_string(), bp_local]);
+            args.extend_from_slice(&["--bibliography".to_string(), bp]);
         }
         args.extend_from_slice(&get_content_file_list());
 
@@ -404,6 +537,7 @@ fn record_build_data(log_lines: &[String], meta: &PaperMeta) -> Result<()> {
             fs::write(csl_out_path, refs_str)?;
         }
     }
+    refs::record_bibliography_state(meta)?;
 
     let mut out_file = fs::File::create(
         std::env::current_dir()
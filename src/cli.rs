@@ -3,7 +3,7 @@ use clap::{arg, value_parser, Command};
 pub fn cli() -> Command {
     let build_args = vec![
         arg!(-t --"output-format" <FORMAT> "The desired format of the output file")
-            .value_parser(["docx", "latex", "latex+pdf", "json"])
+            .value_parser(["docx", "latex", "latex+pdf", "html", "json"])
             .default_value("docx"),
         arg!(--"docx-revision" <NUM> "Revision number for docx output format; if unset or negative, will use the number of times the project was saved.")
             .value_parser(value_parser!(i64))
@@ -20,10 +20,12 @@ pub fn cli() -> Command {
             Command::new("new")
                 .about("Create a new directory with the scaffolding for a new writing/research project.")
                 .arg(arg!(<PROJECT_NAME> "The name of the directory to create for the project."))
+                .arg(arg!(--from <FILE> "An existing .docx or .tex document to convert into the project’s content."))
         )
         .subcommand(
             Command::new("init")
                 .about("While in an empty directory, set it up for a project.\n(Called as part of the process for `new`.)")
+                .arg(arg!(--from <FILE> "An existing .docx or .tex document to convert into the project’s content."))
         )
         .subcommand(
             Command::new("dev")
@@ -34,6 +36,8 @@ pub fn cli() -> Command {
             Command::new("build")
                 .about("Generate versions of the paper ready for submission.")
                 .args(&build_args)
+                .arg(arg!(--"verify-reproducible" "Build twice and make sure the output is byte-for-byte identical."))
+                .arg(arg!(--"strip-annotations" "Leave out review comments and tracked changes (accepting insertions and dropping deletions) for a clean submission copy."))
         )
         .subcommand(
             Command::new("save")
@@ -55,11 +59,54 @@ pub fn cli() -> Command {
             )
             .subcommand(
                 Command::new("watch")
-                .about("Watches the content directory and emits new wordcount data on each change, optionally rebuilding.")
+                .about("Watches the content directory, resources, and bibliographies and emits new wordcount data on each change, optionally rebuilding.")
                 .args(&wc_args)
                 .arg(arg!(--build "Rebuild the project before showing word count"))
+                .arg(arg!(--debounce <MS> "How many milliseconds to wait for changes to settle before responding.")
+                    .value_parser(value_parser!(u64)).default_value("300"))
+                .arg(arg!(--serve "Serve a live-reloading HTML preview of the paper on localhost"))
+                .arg(arg!(--port <PORT> "Port for the preview server (with `--serve`).")
+                    .value_parser(value_parser!(u16)).default_value("8000"))
                 .args(&build_args)
         )
+        .subcommand(
+            Command::new("meta")
+                .about("Work with the project’s `paper_meta.yml`.")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("check")
+                        .about("Check `paper_meta.yml` for unknown keys and invalid values.")
+                )
+        )
+        .subcommand(
+            Command::new("refs")
+                .about("Manage the project’s bibliography sources.")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("sync")
+                        .about("Pull references from the `refs.sync_from` Zotero export path or local HTTP endpoint into the project bibliography.")
+                )
+        )
+        .subcommand(
+            Command::new("resources")
+                .about("Manage the project’s copy of paper’s templates, filters, and styles.")
+                .arg_required_else_help(true)
+                .subcommand(
+                    Command::new("update")
+                        .about("Bring `.paper_resources` up to date with the installed version of paper, leaving locally modified files alone.")
+                        .arg(arg!(--force "Replace locally modified files too."))
+                )
+        )
+        .subcommand(
+            Command::new("import-comments")
+                .about("Pull the comments out of a returned Word document and match them up with the content files.")
+                .arg(arg!(<DOCX_FILE> "The commented docx file."))
+                .arg(arg!(--inline "Put the comments into the content files as annotations instead of writing a report to the research directory."))
+        )
+        .subcommand(
+            Command::new("doctor")
+                .about("Check that everything paper needs is installed and working.")
+        )
         .subcommand(
             Command::new("fmt")
                 .about("Run an automated formatter on all the local Markdown files.")
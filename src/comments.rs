@@ -0,0 +1,597 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use sxd_document::dom::Element;
+
+use crate::build;
+use crate::config::CONFIG;
+use crate::docx;
+use crate::docx_passes::{child_elements, w, DocxPackage, DOCUMENT_PART};
+use crate::util;
+
+const COMMENTS_PART: &str = "word/comments.xml";
+// how much a docx paragraph has to look like a Markdown one before we'll
+//   say that's where the comment goes
+const MATCH_THRESHOLD: f64 = 0.2;
+
+struct ImportedComment {
+    id: String,
+    author: String,
+    date: Option<String>,
+    note: String,
+}
+
+// where a comment was anchored in the returned document
+struct Anchor {
+    paragraph: usize,
+    text: String,
+}
+
+// a paragraph-ish chunk of a content file, separated by blank lines
+struct MarkdownBlock {
+    file: String,
+    line: usize,
+    start: usize,
+    end: usize,
+}
+
+struct Placement {
+    block: usize,
+    // byte range of the anchor text in the file, if it was found verbatim
+    anchor_range: Option<(usize, usize)>,
+    line: usize,
+}
+
+pub fn import_comments(docx_path: &str, inline: bool) -> Result<()> {
+    util::ensure_paper_dir()?;
+
+    let docx_path = Path::new(docx_path);
+    if !docx_path.exists() {
+        bail!("No file at {:?}", docx_path);
+    }
+
+    let unzip_dir = tempfile::tempdir().context("Could not create temporary directory")?;
+    if CONFIG.get().verbose {
+        println!("Unzipping {:?}...", docx_path);
+    }
+    docx::unzip_docx(docx_path, unzip_dir.path())?;
+    let pkg = DocxPackage::new(unzip_dir.path());
+
+    if !pkg.has_part(COMMENTS_PART) {
+        println!("No comments in {:?}.", docx_path);
+        return Ok(());
+    }
+    let comments = get_comments(&pkg)?;
+    if comments.is_empty() {
+        println!("No comments in {:?}.", docx_path);
+        return Ok(());
+    }
+    let (paragraphs, anchors) = get_anchors(&pkg)?;
+
+    let mut sources = HashMap::new();
+    let mut blocks = vec![];
+    for cf in build::get_content_file_list() {
+        let source =
+            fs::read_to_string(&cf).with_context(|| format!("Could not read file {:?}", cf))?;
+        blocks.extend(get_blocks(&cf, &source));
+        sources.insert(cf, source);
+    }
+
+    let mut placed = vec![];
+    let mut unplaced = vec![];
+    for comment in &comments {
+        let placement = anchors
+            .get(&comment.id)
+            .and_then(|a| place_comment(a, &paragraphs[a.paragraph], &blocks, &sources));
+        match placement {
+            Some(p) => placed.push((comment, p)),
+            None => unplaced.push(comment),
+        }
+    }
+    if CONFIG.get().verbose {
+        println!(
+            "Matched {} of {} comment(s) to the content files.",
+            placed.len(),
+            comments.len()
+        );
+    }
+
+    if inline {
+        write_inline(&placed, &blocks, &mut sources, &anchors)?;
+        // the ones with nowhere to go still need to land somewhere
+        if !unplaced.is_empty() {
+            eprintln!(
+                "[WARNING] Couldn't find where {} comment(s) go; putting them in a feedback report instead.",
+                unplaced.len()
+            );
+            write_report(docx_path, &[], &unplaced, &blocks, &anchors)?;
+        }
+    } else {
+        write_report(docx_path, &placed, &unplaced, &blocks, &anchors)?;
+    }
+
+    Ok(())
+}
+
+fn get_comments(pkg: &DocxPackage) -> Result<Vec<ImportedComment>> {
+    let comments_pkg = pkg.read_part(COMMENTS_PART)?;
+    let comments_doc = comments_pkg.as_document();
+
+    let mut comments = vec![];
+    for el in pkg.select(&comments_doc, "//w:comment")? {
+        let paras: Vec<String> = child_elements(el)
+            .into_iter()
+            .filter(|p| p.name() == w("p"))
+            .map(|p| {
+                let mut text = String::new();
+                collect_text(p, &mut text);
+                text.trim().to_string()
+            })
+            .collect();
+        comments.push(ImportedComment {
+            id: el.attribute_value(w("id")).unwrap_or_default().to_string(),
+            author: el
+                .attribute_value(w("author"))
+                .unwrap_or("Unknown")
+                .to_string(),
+            date: el.attribute_value(w("date")).map(String::from),
+            note: paras.join("\n").trim().to_string(),
+        });
+    }
+    Ok(comments)
+}
+
+fn collect_text(el: Element, text: &mut String) {
+    for child in child_elements(el) {
+        if child.name() == w("t") {
+            for t in child.children().into_iter().filter_map(|c| c.text()) {
+                text.push_str(t.text());
+            }
+        } else if child.name() == w("tab") {
+            text.push(' ');
+        } else {
+            collect_text(child, text);
+        }
+    }
+}
+
+// returns the text of every paragraph in the document, along with where
+//   each comment was anchored
+fn get_anchors(pkg: &DocxPackage) -> Result<(Vec<String>, HashMap<String, Anchor>)> {
+    let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+    let doc = doc_pkg.as_document();
+
+    let mut paragraphs = vec![];
+    let mut anchors = HashMap::new();
+    let mut open = vec![];
+    for para in pkg.select(&doc, "//w:body//w:p[not(ancestor::w:p)]")? {
+        let mut text = String::new();
+        walk_paragraph(para, paragraphs.len(), &mut text, &mut open, &mut anchors);
+        // ranges that span paragraphs shouldn't run their words together
+        for id in &open {
+            if let Some(a) = anchors.get_mut(id) {
+                a.text.push(' ');
+            }
+        }
+        paragraphs.push(text);
+    }
+    Ok((paragraphs, anchors))
+}
+
+fn walk_paragraph(
+    el: Element,
+    para_idx: usize,
+    text: &mut String,
+    open: &mut Vec<String>,
+    anchors: &mut HashMap<String, Anchor>,
+) {
+    for child in child_elements(el) {
+        let id = child
+            .attribute_value(w("id"))
+            .unwrap_or_default()
+            .to_string();
+        if child.name() == w("commentRangeStart") {
+            anchors.entry(id.clone()).or_insert(Anchor {
+                paragraph: para_idx,
+                text: String::new(),
+            });
+            open.push(id);
+        } else if child.name() == w("commentRangeEnd") {
+            open.retain(|o| *o != id);
+        } else if child.name() == w("commentReference") {
+            // comments on a single point rather than a range only have this
+            anchors.entry(id).or_insert(Anchor {
+                paragraph: para_idx,
+                text: String::new(),
+            });
+        } else if child.name() == w("t") || child.name() == w("tab") {
+            let mut run_text = String::new();
+            if child.name() == w("tab") {
+                run_text.push(' ');
+            } else {
+                for t in child.children().into_iter().filter_map(|c| c.text()) {
+                    run_text.push_str(t.text());
+                }
+            }
+            text.push_str(&run_text);
+            for o in open.iter() {
+                if let Some(a) = anchors.get_mut(o) {
+                    a.text.push_str(&run_text);
+                }
+            }
+        } else {
+            walk_paragraph(child, para_idx, text, open, anchors);
+        }
+    }
+}
+
+fn get_blocks(file: &str, source: &str) -> Vec<MarkdownBlock> {
+    let mut blocks = vec![];
+    let mut current: Option<MarkdownBlock> = None;
+    let mut offset = 0;
+    for (i, line) in source.split_inclusive('\n').enumerate() {
+        if line.trim().is_empty() {
+            if let Some(b) = current.take() {
+                blocks.push(b);
+            }
+        } else {
+            let end = offset + line.trim_end().len();
+            match &mut current {
+                Some(b) => b.end = end,
+                None => {
+                    current = Some(MarkdownBlock {
+                        file: file.to_string(),
+                        line: i + 1,
+                        start: offset,
+                        end,
+                    })
+                }
+            }
+        }
+        offset += line.len();
+    }
+    if let Some(b) = current {
+        blocks.push(b);
+    }
+    blocks
+}
+
+// where `needle` shows up in `haystack` without starting or ending partway
+//   through a word, so a comment on "he" doesn't wrap half of "the"
+fn find_words(haystack: &str, needle: &str) -> Option<usize> {
+    let is_word = |c: Option<char>| c.is_some_and(|c| c.is_alphanumeric());
+    let starts_word = is_word(needle.chars().next());
+    let ends_word = is_word(needle.chars().next_back());
+    haystack
+        .char_indices()
+        .map(|(pos, _)| pos)
+        .filter(|pos| haystack[*pos..].starts_with(needle))
+        .find(|pos| {
+            let before = haystack[..*pos].chars().next_back();
+            let after = haystack[*pos + needle.len()..].chars().next();
+            !((starts_word && is_word(before)) || (ends_word && is_word(after)))
+        })
+}
+
+fn place_comment(
+    anchor: &Anchor,
+    paragraph: &str,
+    blocks: &[MarkdownBlock],
+    sources: &HashMap<String, String>,
+) -> Option<Placement> {
+    let para_words = util::get_words(paragraph);
+    let anchor_text = anchor.text.trim();
+
+    let mut best: Option<(f64, Placement)> = None;
+    for (i, block) in blocks.iter().enumerate() {
+        let source = &sources[&block.file];
+        let block_text = &source[block.start..block.end];
+        let block_words = util::get_words(block_text);
+        let union = para_words.union(&block_words).count();
+        let mut score = if union == 0 {
+            0.0
+        } else {
+            para_words.intersection(&block_words).count() as f64 / union as f64
+        };
+
+        let mut anchor_range = None;
+        let mut line = block.line;
+        if !anchor_text.is_empty() {
+            if let Some(pos) = find_words(block_text, anchor_text) {
+                score += 1.0;
+                let start = block.start + pos;
+                anchor_range = Some((start, start + anchor_text.len()));
+                line += block_text[..pos].matches('\n').count();
+            }
+        }
+
+        if score >= MATCH_THRESHOLD && best.as_ref().is_none_or(|(s, _)| score > *s) {
+            best = Some((
+                score,
+                Placement {
+                    block: i,
+                    anchor_range,
+                    line,
+                },
+            ));
+        }
+    }
+
+    best.map(|(_, p)| p)
+}
+
+fn escape_attribute(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .lines()
+        .map(|l| l.trim())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// (start, end, replacement) for each file
+fn get_inline_edits(
+    placed: &[(&ImportedComment, Placement)],
+    blocks: &[MarkdownBlock],
+    anchors: &HashMap<String, Anchor>,
+) -> HashMap<String, Vec<(usize, usize, String)>> {
+    let mut edits: HashMap<String, Vec<(usize, usize, String)>> = HashMap::new();
+    for (comment, placement) in placed {
+        let block = &blocks[placement.block];
+        let mut attrs = format!("author=\"{}\"", escape_attribute(&comment.author));
+        if let Some(date) = &comment.date {
+            attrs.push_str(&format!(" date=\"{}\"", escape_attribute(date)));
+        }
+        attrs.push_str(&format!(" note=\"{}\"", escape_attribute(&comment.note)));
+
+        let file_edits = edits.entry(block.file.clone()).or_default();
+        // overlapping comments can't both wrap the same text, so later ones
+        //   just go at the end of the paragraph
+        let range = placement.anchor_range.filter(|(start, end)| {
+            !file_edits
+                .iter()
+                .any(|(s, e, _)| start < e && s < end && s != e)
+        });
+        match range {
+            Some((start, end)) => {
+                let anchor_text = anchors
+                    .get(&comment.id)
+                    .map(|a| a.text.trim().to_string())
+                    .unwrap_or_default();
+                file_edits.push((
+                    start,
+                    end,
+                    format!("[{}]{{.comment {}}}", anchor_text, attrs),
+                ));
+            }
+            None => file_edits.push((block.end, block.end, format!(" []{{.comment {}}}", attrs))),
+        }
+    }
+    edits
+}
+
+fn apply_edits(source: &mut String, file_edits: &mut [(usize, usize, String)]) {
+    // from the back, so earlier offsets stay good
+    file_edits.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+    for (start, end, replacement) in file_edits.iter() {
+        source.replace_range(start..end, replacement);
+    }
+}
+
+fn write_inline(
+    placed: &[(&ImportedComment, Placement)],
+    blocks: &[MarkdownBlock],
+    sources: &mut HashMap<String, String>,
+    anchors: &HashMap<String, Anchor>,
+) -> Result<()> {
+    let mut count = 0;
+    for (file, mut file_edits) in get_inline_edits(placed, blocks, anchors) {
+        let source = sources
+            .get_mut(&file)
+            .context("Lost track of a content file")?;
+        apply_edits(source, &mut file_edits);
+        if CONFIG.get().verbose {
+            println!("Adding {} comment(s) to {}...", file_edits.len(), file);
+        }
+        fs::write(&file, &source).with_context(|| format!("Could not write file {:?}", file))?;
+        count += file_edits.len();
+    }
+    println!("Added {} comment(s) to the content files.", count);
+
+    Ok(())
+}
+
+fn write_report(
+    docx_path: &Path,
+    placed: &[(&ImportedComment, Placement)],
+    unplaced: &[&ImportedComment],
+    blocks: &[MarkdownBlock],
+    anchors: &HashMap<String, Anchor>,
+) -> Result<()> {
+    let today = chrono::Local::now().format("%Y-%m-%d").to_string();
+    let research_dir = Path::new("research");
+    if !research_dir.exists() {
+        fs::create_dir(research_dir).context("Could not create research directory.")?;
+    }
+    let mut report_path = research_dir.join(format!("feedback-{}.md", today));
+    let mut n = 2;
+    while report_path.exists() {
+        report_path = research_dir.join(format!("feedback-{}-{}.md", today, n));
+        n += 1;
+    }
+
+    let file_name = docx_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let mut report = format!(
+        "# Feedback from `{}`\n\nImported {} with `paper import-comments`.\n",
+        file_name, today
+    );
+
+    let mut entry = |comment: &ImportedComment, heading: String| {
+        report.push_str(&format!("\n## {}\n\n", heading));
+        if let Some(anchor) = anchors.get(&comment.id) {
+            let anchor_text = anchor.text.trim();
+            if !anchor_text.is_empty() {
+                report.push_str(&format!("> {}\n\n", anchor_text));
+            }
+        }
+        let date = comment
+            .date
+            .as_deref()
+            .map(|d| format!(" ({})", d.get(..10).unwrap_or(d)))
+            .unwrap_or_default();
+        report.push_str(&format!(
+            "**{}**{}: {}\n",
+            comment.author, date, comment.note
+        ));
+    };
+
+    let mut sorted: Vec<_> = placed.iter().collect();
+    sorted.sort_by(|a, b| {
+        (&blocks[a.1.block].file, a.1.line).cmp(&(&blocks[b.1.block].file, b.1.line))
+    });
+    for (comment, placement) in sorted {
+        entry(
+            comment,
+            format!("{}:{}", blocks[placement.block].file, placement.line),
+        );
+    }
+    for comment in unplaced {
+        entry(comment, "(couldn't find where this goes)".to_string());
+    }
+
+    fs::write(&report_path, report)
+        .with_context(|| format!("Could not write file {:?}", report_path))?;
+    println!(
+        "Wrote {} comment(s) to {}.",
+        placed.len() + unplaced.len(),
+        report_path.to_string_lossy()
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "# The Title\n\nThe first paragraph is about\nthe ethics of virtue.\n\n\nThe second one says \"hello\" here.\n";
+
+    fn setup() -> (Vec<MarkdownBlock>, HashMap<String, String>) {
+        let blocks = get_blocks("content/01.md", SOURCE);
+        let sources = HashMap::from([("content/01.md".to_string(), SOURCE.to_string())]);
+        (blocks, sources)
+    }
+
+    fn comment(id: &str, note: &str) -> ImportedComment {
+        ImportedComment {
+            id: id.to_string(),
+            author: "Prof. X".to_string(),
+            date: None,
+            note: note.to_string(),
+        }
+    }
+
+    fn anchor(text: &str) -> Anchor {
+        Anchor {
+            paragraph: 0,
+            text: text.to_string(),
+        }
+    }
+
+    #[test]
+    fn blocks() {
+        let (blocks, _) = setup();
+        let found: Vec<_> = blocks
+            .iter()
+            .map(|b| (b.line, &SOURCE[b.start..b.end]))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (1, "# The Title"),
+                (3, "The first paragraph is about\nthe ethics of virtue."),
+                (7, "The second one says \"hello\" here."),
+            ]
+        );
+    }
+
+    #[test]
+    fn whole_words_only() {
+        assert_eq!(find_words("the hero", "he"), None);
+        assert_eq!(find_words("the he", "he"), Some(4));
+        assert_eq!(find_words("then the end", "the"), Some(5));
+        assert_eq!(find_words("say \"hello\"", "\"hello\""), Some(4));
+        assert_eq!(find_words("(aside)", "aside"), Some(1));
+    }
+
+    #[test]
+    fn placing() {
+        let (blocks, sources) = setup();
+        let paragraph = "The first paragraph is about the ethics of virtue.";
+
+        let p = place_comment(&anchor("ethics"), paragraph, &blocks, &sources).unwrap();
+        assert_eq!(p.block, 1);
+        assert_eq!(p.line, 4);
+        let (start, end) = p.anchor_range.unwrap();
+        assert_eq!(&SOURCE[start..end], "ethics");
+
+        // "he" is only ever inside other words here, so it lands on the
+        //   paragraph without wrapping anything
+        let p = place_comment(&anchor("he"), paragraph, &blocks, &sources).unwrap();
+        assert_eq!(p.block, 1);
+        assert_eq!(p.anchor_range, None);
+
+        let nowhere = place_comment(
+            &anchor("zebra"),
+            "Nothing like anything in the paper.",
+            &blocks,
+            &sources,
+        );
+        assert!(nowhere.is_none());
+    }
+
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            escape_attribute("say \"hi\"\n  then go\\"),
+            "say \\\"hi\\\" then go\\\\"
+        );
+    }
+
+    #[test]
+    fn inline_edits() {
+        let (blocks, sources) = setup();
+        let paragraph = "The first paragraph is about the ethics of virtue.";
+        let anchors = HashMap::from([
+            ("1".to_string(), anchor("ethics")),
+            ("2".to_string(), anchor("ethics of virtue")),
+            ("3".to_string(), anchor("virtue")),
+        ]);
+        let comments = [
+            comment("1", "Which?"),
+            comment("2", "Overlaps"),
+            comment("3", "Good"),
+        ];
+        let placed: Vec<_> = comments
+            .iter()
+            .map(|c| {
+                let a = &anchors[&c.id];
+                (c, place_comment(a, paragraph, &blocks, &sources).unwrap())
+            })
+            .collect();
+
+        let mut edits = get_inline_edits(&placed, &blocks, &anchors);
+        let mut source = sources["content/01.md"].clone();
+        apply_edits(&mut source, edits.get_mut("content/01.md").unwrap());
+        assert_eq!(
+            source,
+            "# The Title\n\nThe first paragraph is about\nthe [ethics]{.comment author=\"Prof. X\" note=\"Which?\"} of [virtue]{.comment author=\"Prof. X\" note=\"Good\"}. []{.comment author=\"Prof. X\" note=\"Overlaps\"}\n\n\nThe second one says \"hello\" here.\n"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+// A quick look over everything paper depends on, so problems show up as a
+//   readable checklist instead of a "Command Runtime Error" halfway through
+//   a build.
+
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Result};
+
+use crate::config::CONFIG;
+use crate::metadata::PaperMeta;
+use crate::pandoc_wrap;
+use crate::refs;
+use crate::subprocess;
+use crate::tectonic_wrap;
+use crate::util;
+
+// everything the builders hand to pandoc or read themselves; the title page
+//   template is left out since there's a built-in fallback for it
+const REQUIRED_RESOURCES: &[&str] = &[
+    "ChicagoStyle_Template.docx",
+    "ChicagoStyle_NoTitlePage_Template.docx",
+    "ChicagoStyle_Template.tex",
+    "chicago-fullnote-bibliography-short-title-subsequent.csl",
+    "chicago-fullnote-bibliography-with-ibid.csl",
+];
+
+#[derive(PartialEq)]
+enum Status {
+    Pass,
+    Warn,
+    Fail,
+}
+
+struct Check {
+    name: String,
+    status: Status,
+    detail: String,
+    // what to do about it, if it's not a pass
+    hint: Option<String>,
+}
+
+impl Check {
+    fn pass(name: &str, detail: String) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Pass,
+            detail,
+            hint: None,
+        }
+    }
+
+    fn warn(name: &str, detail: String, hint: &str) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Warn,
+            detail,
+            hint: Some(hint.to_string()),
+        }
+    }
+
+    fn fail(name: &str, detail: String, hint: &str) -> Check {
+        Check {
+            name: name.to_string(),
+            status: Status::Fail,
+            detail,
+            hint: Some(hint.to_string()),
+        }
+    }
+}
+
+// first line of `<exe> --version`, if it runs at all
+fn get_version_line(exe: &str) -> Option<String> {
+    subprocess::run_command(exe, &["--version"], None, false)
+        .ok()
+        .and_then(|out| out.lines().next().map(|l| l.trim().to_string()))
+}
+
+fn check_git() -> Check {
+    match util::find_executable("git") {
+        None => Check::fail(
+            "git",
+            "not found".to_string(),
+            "Install git; `paper new` and `paper save` can't work without it.",
+        ),
+        Some(p) => match get_version_line(&p.to_string_lossy()) {
+            Some(v) => Check::pass("git", v),
+            None => Check::fail("git", format!("{:?} doesn't run", p), "Reinstall git."),
+        },
+    }
+}
+
+fn check_gh() -> Check {
+    let gh = match util::find_executable("gh") {
+        Some(p) => p,
+        None => {
+            return Check::warn(
+                "gh",
+                "not found".to_string(),
+                "Only needed for `paper push` (to make the GitHub repository) and `paper web`; install the GitHub CLI if you want those.",
+            )
+        }
+    };
+    let version = get_version_line(&gh.to_string_lossy()).unwrap_or_default();
+    match subprocess::run_command(&gh.to_string_lossy(), &["auth", "status"], None, false) {
+        Ok(_) => Check::pass("gh", version),
+        Err(_) => Check::warn(
+            "gh",
+            format!("{} (not logged in)", version),
+            "Run `gh auth login` before using `paper push`.",
+        ),
+    }
+}
+
+fn check_pandoc() -> Check {
+    // only looking; a checkup shouldn't go downloading things
+    let (exe_path, source) = match pandoc_wrap::find_installed_pandoc() {
+        Some(found) => found,
+        None => {
+            let checked = if pandoc_wrap::has_pinned_hash() {
+                "and checked against its pinned SHA-256"
+            } else {
+                "though there's no pinned SHA-256 to check it against"
+            };
+            return Check::warn(
+                "pandoc",
+                "not installed yet".to_string(),
+                &format!(
+                    "v{} gets downloaded on the first build ({}); set PAPER_PANDOC_ARCHIVE to a release archive if there's no network.",
+                    pandoc_wrap::PANDOC_LOCKED_VERSION,
+                    checked
+                ),
+            );
+        }
+    };
+    let version = match pandoc_wrap::get_pandoc_version(&exe_path) {
+        Ok(v) => v,
+        Err(e) => {
+            let hint = match source {
+                pandoc_wrap::PandocSource::Managed => format!(
+                    "Delete {:?} and build again to reinstall it (from PAPER_PANDOC_ARCHIVE if there's no network).",
+                    exe_path.parent().unwrap_or(&exe_path)
+                ),
+                pandoc_wrap::PandocSource::System => {
+                    "Fix or remove the pandoc on the PATH.".to_string()
+                }
+            };
+            return Check::fail(
+                "pandoc",
+                format!("{:?} doesn't run: {:#}", exe_path, e),
+                &hint,
+            );
+        }
+    };
+    let detail = format!("v{} ({}, at {:?})", version, source, exe_path);
+    if version != pandoc_wrap::PANDOC_LOCKED_VERSION {
+        Check::warn(
+            "pandoc",
+            detail,
+            &format!(
+                "Output may differ from what v{} would give you.",
+                pandoc_wrap::PANDOC_LOCKED_VERSION
+            ),
+        )
+    } else if source == pandoc_wrap::PandocSource::System {
+        Check::warn(
+            "pandoc",
+            detail,
+            "Couldn't get the pinned copy; using the one on the PATH.",
+        )
+    } else {
+        Check::pass("pandoc", detail)
+    }
+}
+
+fn check_latex_engine(meta: Option<&PaperMeta>) -> Check {
+    let engine = meta
+        .and_then(|m| m.latex.engine.clone())
+        .unwrap_or("tectonic".to_string());
+
+    if engine != "tectonic" {
+        return match util::find_executable(&engine) {
+            Some(p) => Check::pass(
+                "latex engine",
+                get_version_line(&p.to_string_lossy()).unwrap_or(engine),
+            ),
+            None => Check::fail(
+                "latex engine",
+                format!("`{}` not found", engine),
+                "Install it, or take `latex.engine` out of the meta file to use Tectonic.",
+            ),
+        };
+    }
+
+    if let Err(e) = tectonic_wrap::get_bundle_args() {
+        return Check::fail(
+            "tectonic",
+            format!("{:#}", e),
+            "Fix the `tectonic` section of the user config.",
+        );
+    }
+    match tectonic_wrap::find_installed_tectonic() {
+        Err(e) => Check::fail(
+            "tectonic",
+            format!("{:#}", e),
+            "Fix the `tectonic` section of the user config.",
+        ),
+        Ok(None) => match tectonic_wrap::get_failed_download() {
+            Some(reason) => Check::fail(
+                "tectonic",
+                format!("download thrown out: {}", reason.trim()),
+                "Set `tectonic.path` in the user config to a Tectonic you've checked yourself, or put one on the PATH.",
+            ),
+            None => {
+                let checked = if tectonic_wrap::has_pinned_hash() {
+                    "and checked against its pinned SHA-256"
+                } else {
+                    "though there's no pinned SHA-256 to check it against"
+                };
+                Check::warn(
+                    "tectonic",
+                    "not installed yet".to_string(),
+                    &format!(
+                        "v{} gets downloaded the first time you build a PDF ({}); set `tectonic.path` in the user config if there's no network.",
+                        tectonic_wrap::TECTONIC_LOCKED_VERSION,
+                        checked
+                    ),
+                )
+            }
+        },
+        Ok(Some(p)) => {
+            let version = tectonic_wrap::get_tectonic_version(&p)
+                .map(|v| format!("v{}", v))
+                .unwrap_or("unknown version".to_string());
+            if version == format!("v{}", tectonic_wrap::TECTONIC_LOCKED_VERSION) {
+                Check::pass("tectonic", format!("{} (at {:?})", version, p))
+            } else {
+                Check::warn(
+                    "tectonic",
+                    format!("{} (at {:?})", version, p),
+                    &format!(
+                        "PDF output may differ from what v{} would give you.",
+                        tectonic_wrap::TECTONIC_LOCKED_VERSION
+                    ),
+                )
+            }
+        }
+    }
+}
+
+fn check_meta() -> (Check, Option<PaperMeta>) {
+    let meta = match PaperMeta::new() {
+        Ok(m) => m,
+        Err(e) => {
+            return (
+                Check::fail(
+                    "meta",
+                    format!("{:#}", e),
+                    "Fix the meta file; `paper meta check` shows what's wrong.",
+                ),
+                None,
+            )
+        }
+    };
+    if let Err(e) = util::get_date_string(&meta) {
+        return (
+            Check::fail(
+                "meta",
+                format!("{:#}", e),
+                "Check `data.date` and `date_format` in the meta file.",
+            ),
+            Some(meta),
+        );
+    }
+    (
+        Check::pass("meta", "loads without problems".to_string()),
+        Some(meta),
+    )
+}
+
+// makes sure each resource file is there and at least looks like what it's
+//   supposed to be
+fn check_resource_file(path: &Path) -> Option<String> {
+    if !path.is_file() {
+        return Some(format!("{:?} is missing", path));
+    }
+    let ext = path
+        .extension()
+        .map(|e| e.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let problem = match ext.as_str() {
+        "docx" => match fs::File::open(path)
+            .ok()
+            .and_then(|f| zip::ZipArchive::new(f).ok())
+        {
+            Some(mut archive) => {
+                if archive.by_name("word/document.xml").is_err() {
+                    Some("has no word/document.xml")
+                } else {
+                    None
+                }
+            }
+            None => Some("isn't a valid docx file"),
+        },
+        "csl" => match fs::read_to_string(path) {
+            Ok(text) => {
+                if sxd_document::parser::parse(&text).is_err() {
+                    Some("isn't valid XML")
+                } else {
+                    None
+                }
+            }
+            Err(_) => Some("can't be read"),
+        },
+        _ => match fs::read_to_string(path) {
+            Ok(text) => {
+                if text.trim().is_empty() {
+                    Some("is empty")
+                } else {
+                    None
+                }
+            }
+            Err(_) => Some("can't be read"),
+        },
+    };
+    problem.map(|p| format!("{:?} {}", path, p))
+}
+
+fn check_resources() -> Check {
+    let resources_path = Path::new(".paper_resources");
+    let mut problems: Vec<String> = REQUIRED_RESOURCES
+        .iter()
+        .filter_map(|r| check_resource_file(&resources_path.join(r)))
+        .collect();
+    let title_page = resources_path.join("TitlePage_Template.md");
+    if title_page.exists() {
+        problems.extend(check_resource_file(&title_page));
+    }
+    if !resources_path.join("filters").is_dir() {
+        problems.push(format!("{:?} is missing", resources_path.join("filters")));
+    }
+
+    if problems.is_empty() {
+        Check::pass(
+            "resources",
+            format!("{} files look fine", REQUIRED_RESOURCES.len()),
+        )
+    } else {
+        Check::fail(
+            "resources",
+            problems.join("; "),
+            "Run `paper resources update` to restore missing files (with `--force` for broken ones).",
+        )
+    }
+}
+
+fn check_bibliography(meta: &PaperMeta) -> Check {
+    let paths = match refs::get_bibliography_paths(meta) {
+        Ok(p) => p,
+        Err(e) => {
+            return Check::fail(
+                "bibliography",
+                format!("{:#}", e),
+                "Check the `sources` list in the meta file.",
+            )
+        }
+    };
+    if paths.is_empty() {
+        return Check::pass("bibliography", "no sources listed".to_string());
+    }
+    let missing: Vec<&String> = paths.iter().filter(|p| !Path::new(p).is_file()).collect();
+    if missing.is_empty() {
+        Check::pass("bibliography", format!("{} source(s) found", paths.len()))
+    } else {
+        Check::fail(
+            "bibliography",
+            format!(
+                "missing {}",
+                missing
+                    .iter()
+                    .map(|m| format!("{:?}", m))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            "Fix the `sources` list in the meta file, or run `paper refs sync` if you use `refs.sync_from`.",
+        )
+    }
+}
+
+fn check_git_remote() -> Check {
+    if !Path::new(".git").exists() {
+        return Check::warn(
+            "git remote",
+            "not a git repository".to_string(),
+            "Run `git init`; `paper save` needs one.",
+        );
+    }
+    let remote = subprocess::run_command("git", &["remote", "-v"], None, false).unwrap_or_default();
+    if remote.trim().is_empty() {
+        return Check::warn(
+            "git remote",
+            "no remote set up".to_string(),
+            "`paper push` will make a GitHub repository for you.",
+        );
+    }
+    let unsaved = subprocess::run_command("git", &["status", "--porcelain"], None, false)
+        .map(|s| s.lines().count())
+        .unwrap_or(0);
+    let unpushed = subprocess::run_command(
+        "git",
+        &["rev-list", "--count", "@{upstream}..HEAD"],
+        None,
+        false,
+    )
+    .ok()
+    .and_then(|s| s.trim().parse::<usize>().ok());
+
+    match unpushed {
+        None => Check::warn(
+            "git remote",
+            "no upstream branch".to_string(),
+            "Run `paper push` to set it up.",
+        ),
+        Some(0) if unsaved == 0 => Check::pass("git remote", "up to date".to_string()),
+        Some(n) => Check::warn(
+            "git remote",
+            format!("{} unpushed commit(s), {} changed file(s)", n, unsaved),
+            "Run `paper save` and then `paper push`.",
+        ),
+    }
+}
+
+pub fn doctor() -> Result<()> {
+    let mut checks = vec![check_git(), check_gh(), check_pandoc()];
+
+    // the rest only makes sense inside a project
+    if util::ensure_paper_dir().is_ok() {
+        let (meta_check, meta) = check_meta();
+        checks.push(check_latex_engine(meta.as_ref()));
+        checks.push(meta_check);
+        checks.push(check_resources());
+        if let Some(meta) = &meta {
+            checks.push(check_bibliography(meta));
+        }
+        checks.push(check_git_remote());
+    } else {
+        checks.push(check_latex_engine(None));
+        if CONFIG.get().verbose {
+            println!("Not in a paper project; skipping the project checks.");
+        }
+    }
+
+    for check in &checks {
+        let label = match check.status {
+            Status::Pass => "[PASS]",
+            Status::Warn => "[WARN]",
+            Status::Fail => "[FAIL]",
+        };
+        println!("{} {}: {}", label, check.name, check.detail);
+        if let Some(hint) = &check.hint {
+            println!("       {}", hint);
+        }
+    }
+
+    let failures = checks.iter().filter(|c| c.status == Status::Fail).count();
+    if failures > 0 {
+        bail!("{} check(s) failed.", failures);
+    }
+    Ok(())
+}
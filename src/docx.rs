@@ -1,25 +1,87 @@
 use std::fs;
 use std::io::{Read, Write};
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Context, Result};
+use anyhow::{Context, Result};
 use filetime::{self, FileTime};
-use sxd_document::QName;
-use sxd_xpath::Value::Nodeset;
 use tempfile::{self, NamedTempFile};
 use walkdir::WalkDir;
 use zip::{self, ZipArchive};
 
 use crate::build;
 use crate::config::CONFIG;
+use crate::docx_passes::{self, DocxPackage, CONTENT_TYPES_PART};
 use crate::formats::Builder;
 use crate::metadata::PaperMeta;
+use crate::pandoc_wrap;
 use crate::subprocess;
 use crate::util;
 
-const DOCX_SCHEMA: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
-const PROP_SCHEMA: &str = "http://schemas.openxmlformats.org/package/2006/metadata/core-properties";
-const DCMD_SCHEMA: &str = "http://purl.org/dc/elements/1.1/";
+const TITLE_PAGE_TEMPLATE: &str = "TitlePage_Template.md";
+
+// shared by the build and by importing comments from a returned docx
+pub fn unzip_docx(docx_path: &Path, output_path: &Path) -> Result<()> {
+    let mut archive: ZipArchive<fs::File>;
+    {
+        let zipped_file = fs::File::open(docx_path)
+            .with_context(|| format!("Could not open file: {:?}", docx_path))?;
+        archive = zip::ZipArchive::new(zipped_file)
+            .with_context(|| format!("Could not open zip archive: {:?}", docx_path))?;
+    }
+
+    for i in 0..archive.len() {
+        let mut file = archive
+            .by_index(i)
+            .with_context(|| format!("Could not get file {} from zip archive", i))?;
+        let filepath = match file.enclosed_name() {
+            Some(path) => path.to_owned(),
+            None => continue,
+        };
+        let creation_path = output_path.join(filepath);
+
+        if (*file.name()).ends_with('/') {
+            fs::create_dir_all(&creation_path).with_context(|| {
+                format!(
+                    "Could not create directory during unzipping {:?}",
+                    &creation_path
+                )
+            })?;
+        } else {
+            if let Some(p) = creation_path.parent() {
+                if !p.exists() {
+                    fs::create_dir_all(p).with_context(|| {
+                        format!("Could not create directory during unzipping {:?}", &p)
+                    })?;
+                }
+            }
+            let mut outfile = fs::File::create(&creation_path).with_context(|| {
+                format!(
+                    "Could not create file during unzipping {:?}",
+                    &creation_path
+                )
+            })?;
+            std::io::copy(&mut file, &mut outfile).with_context(|| {
+                format!("Could not copy file during unzipping {:?}", &file.name())
+            })?;
+        }
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            if let Some(mode) = file.unix_mode() {
+                fs::set_permissions(&creation_path, fs::Permissions::from_mode(mode))
+                    .with_context(|| {
+                        format!(
+                            "Could not set permissions on path during unzipping {:?}",
+                            &creation_path
+                        )
+                    })?;
+            }
+        }
+    }
+
+    Ok(())
+}
 
 #[derive(Default)]
 pub struct DocxBuilder {
@@ -32,23 +94,35 @@ impl Builder for DocxBuilder {
     }
 
     fn prepare(&mut self, args: &mut Vec<String>, meta: &PaperMeta) -> Result<()> {
-        let cmds;
-        if meta.get_bool(&["no_title_page"]).unwrap_or(false) {
-            cmds = [
+        let cmds = if meta.no_title_page {
+            [
                 "--to=docx".to_string(),
                 "--reference-doc".to_string(),
                 ".paper_resources/ChicagoStyle_NoTitlePage_Template.docx".to_string(),
-            ];
+            ]
         } else {
-            cmds = [
+            [
                 "--to=docx".to_string(),
                 "--reference-doc".to_string(),
                 ".paper_resources/ChicagoStyle_Template.docx".to_string(),
-            ];
-        }
+            ]
+        };
         args.extend_from_slice(&cmds);
 
-        if !meta.get_bool(&["no_title_page"]).unwrap_or(false) {
+        // marks up review annotations for the docx passes to find; the
+        //   markers are only cleaned up by that pass, and Word won't open
+        //   a file that still has them, so skip it if the pass is off
+        if docx_passes::is_pass_enabled(meta, "annotations") {
+            let annotations_path = CONFIG
+                .get()
+                .resources_path
+                .join("scripts")
+                .join("docx_annotations.lua");
+            args.push("--lua-filter".to_string());
+            args.push(annotations_path.to_string_lossy().to_string());
+        }
+
+        if !meta.no_title_page {
             let outpath = Path::new(&CONFIG.get().output_directory_name);
             let mut title_page_file = tempfile::Builder::new()
                 .prefix("title-page")
@@ -60,73 +134,9 @@ impl Builder for DocxBuilder {
                 println!("Generating title page into {:?}...", title_page_file.path());
             }
 
-            let mut title_string_coll: Vec<String> = vec![];
-
-            title_string_coll.push("::: title-page\n\n".to_string());
-
-            let title = meta.get_string(&["data", "title"]);
-            let subtitle = meta.get_string(&["data", "subtitle"]);
-
-            if title.is_some() || subtitle.is_some() {
-                title_string_coll.push("::: {custom-style=\"Title\"}\n".to_string());
-                match title {
-                    Some(title_str) => {
-                        title_string_coll.push(title_str);
-                        match subtitle {
-                            Some(subtitle_str) => {
-                                title_string_coll.push(":\\\n".to_string());
-                                title_string_coll.push(subtitle_str);
-                                title_string_coll.push("\n".to_string());
-                            }
-                            None => title_string_coll.push("\n".to_string()),
-                        }
-                    }
-                    None => {
-                        if let Some(subtitle_str) = subtitle {
-                            title_string_coll.push(subtitle_str);
-                            title_string_coll.push("\n".to_string());
-                        }
-                    }
-                }
-                title_string_coll.push(":::\n".to_string());
-            }
-            title_string_coll.push("::: {custom-style=\"Author\"}\nby\n:::\n".to_string());
-            title_string_coll.push("::: {custom-style=\"Author\"}\n".to_string());
-            if let Some(author_str) = meta.get_string(&["data", "author"]) {
-                title_string_coll.push(author_str);
-                title_string_coll.push("\n".to_string());
-            }
-            title_string_coll.push(":::\n".to_string());
-
-            title_string_coll.push("::: {custom-style=\"Author\"}\n".to_string());
+            let title_page = self.render_title_page(meta)?;
 
-            meta.get_string(&["data", "professor"])
-                .map(|prof_str| title_string_coll.push(format!("{}\\\n", prof_str)));
-
-            // I wonder if there's some more elegant way of handling this, but it's a pretty awkward
-            //    bit of logic no matter what
-            match meta.get_string(&["data", "class_mnemonic"]) {
-                Some(mnemonic_str) => {
-                    title_string_coll.push(mnemonic_str);
-                    if let Some(classname_str) = meta.get_string(&["data", "class_name"]) {
-                        title_string_coll.push(format!(" --- {}", classname_str));
-                    }
-                }
-                None => {
-                    if let Some(classname_str) = meta.get_string(&["data", "class_name"]) {
-                        title_string_coll.push(classname_str);
-                    }
-                }
-            }
-
-            title_string_coll.push("\\\n".to_string());
-            title_string_coll.push(util::get_date_string(meta)?);
-
-            title_string_coll.push("\n:::\n".to_string());
-
-            title_string_coll.push("\n:::\n".to_string());
-
-            write!(title_page_file, "{}", title_string_coll.join(""))
+            write!(title_page_file, "{}", title_page)
                 .context("Could not write to temporary title page file.")?;
 
             self.tmp_prefix_files.push(title_page_file);
@@ -151,15 +161,7 @@ impl Builder for DocxBuilder {
 
     fn finish_file(&self, output_file_path: &Path, meta: &PaperMeta) -> Result<Vec<String>> {
         if CONFIG.get().verbose {
-            println!("Packinging docx...");
-        }
-
-        let mut archive: ZipArchive<fs::File>;
-        {
-            let zipped_file = fs::File::open(output_file_path)
-                .with_context(|| format!("Could not open file: {:?}", output_file_path))?;
-            archive = zip::ZipArchive::new(zipped_file)
-                .with_context(|| format!("Could not open zip archive: {:?}", output_file_path))?;
+            println!("Packaging docx...");
         }
 
         if CONFIG.get().verbose {
@@ -168,184 +170,9 @@ impl Builder for DocxBuilder {
         let output_dir =
             tempfile::tempdir_in("").context("Could not create temporary directory")?;
         let output_path = output_dir.path();
+        unzip_docx(output_file_path, output_path)?;
 
-        for i in 0..archive.len() {
-            let mut file = archive
-                .by_index(i)
-                .with_context(|| format!("Could not get file {} from zip archive", i))?;
-            let filepath = match file.enclosed_name() {
-                Some(path) => path.to_owned(),
-                None => continue,
-            };
-            let creation_path = output_path.join(filepath);
-
-            if (*file.name()).ends_with('/') {
-                fs::create_dir_all(&creation_path).with_context(|| {
-                    format!(
-                        "Could not create directory during unzipping {:?}",
-                        &creation_path
-                    )
-                })?;
-            } else {
-                if let Some(p) = creation_path.parent() {
-                    if !p.exists() {
-                        fs::create_dir_all(p).with_context(|| {
-                            format!("Could not create directory during unzipping {:?}", &p)
-                        })?;
-                    }
-                }
-                let mut outfile = fs::File::create(&creation_path).with_context(|| {
-                    format!(
-                        "Could not create file during unzipping {:?}",
-                        &creation_path
-                    )
-                })?;
-                std::io::copy(&mut file, &mut outfile).with_context(|| {
-                    format!("Could not copy file during unzipping {:?}", &file.name())
-                })?;
-            }
-
-            #[cfg(unix)]
-            {
-                use std::os::unix::fs::PermissionsExt;
-                if let Some(mode) = file.unix_mode() {
-                    fs::set_permissions(&creation_path, fs::Permissions::from_mode(mode))
-                        .with_context(|| {
-                            format!(
-                                "Could not set permissions on path during unzipping {:?}",
-                                &creation_path
-                            )
-                        })?;
-                }
-            }
-        }
-
-        let mut context = sxd_xpath::Context::new();
-        context.set_namespace("w", DOCX_SCHEMA);
-        context.set_namespace("cp", PROP_SCHEMA);
-        context.set_namespace("dc", DCMD_SCHEMA);
-        let factory = sxd_xpath::Factory::new();
-
-        // the pandoc writer doesn't check the "total row" box for tables unless
-        //   they explicitly have a footer, and we use that to do the spacing since
-        //   Word doesn't have a good way to add spacing after the *whole* table
-        if CONFIG.get().verbose {
-            println!("Fixing docx table post-spacing styles...");
-        }
-        let doc_doc = self
-            .get_file_root(output_path, "word/document.xml")
-            .with_context(|| format!("Could not get file root for {:?}", &output_path))?;
-        let doc_doc = doc_doc.as_document();
-        let root = doc_doc.root();
-
-        let xpath = self.get_xpath(&factory, "//w:tblLook")?;
-        let val = xpath
-            .evaluate(&context, root)
-            .context("Could not evaluate xpath")?;
-        if let Nodeset(ns) = val {
-            for node in ns {
-                if let Some(el) = node.element() {
-                    let name = QName::with_namespace_uri(
-                        Some("http://schemas.openxmlformats.org/wordprocessingml/2006/main"),
-                        "lastRow",
-                    );
-                    el.set_attribute_value(name, "1");
-                }
-            }
-        } else {
-            bail!("XPath did not return Nodeset");
-        }
-
-        // pandoc with version 3 started emitting a node to align all tables to the
-        //   leading edge of their text.
-        if CONFIG.get().verbose {
-            println!("Fixing docx table alignmnent...");
-        }
-
-        let xpath = self.get_xpath(&factory, "//w:tblPr/w:jc")?;
-        let val = xpath
-            .evaluate(&context, root)
-            .context("Could not evaluate xpath")?;
-        if let Nodeset(ns) = val {
-            for node in ns {
-                if let Some(el) = node.element() {
-                    let name = QName::with_namespace_uri(
-                        Some("http://schemas.openxmlformats.org/wordprocessingml/2006/main"),
-                        "val",
-                    );
-                    el.set_attribute_value(name, "center");
-                }
-            }
-        } else {
-            bail!("XPath did not return Nodeset");
-        }
-
-        self.write_document(&doc_doc, output_path, "word/document.xml")?;
-
-        // change fonts (if needed) in Normal and Verbatim Char styles
-        if meta.contains(&["base_font_override"]) || meta.contains(&["mono_font_override"]) {
-            // I wanted to do this more cleverly with XML, but
-            //  sxd-document doesn't fully round-trip and MS Word chokes on the
-            //  result even though it's semantically identical. (Unused xmlns attributes
-            //  get trimmed, which Word expects for some reason.)
-            // Anyway, the XML-based solution is still in the git history
-            //  if this makes you sad, as it does me.
-            let styles_path = output_path.join("word/styles.xml");
-            let mut styles_datums = String::new();
-            {
-                let mut styles_file = fs::File::open(&styles_path)
-                    .with_context(|| format!("Could not open styles file {:?}", &styles_path))?;
-                styles_file
-                    .read_to_string(&mut styles_datums)
-                    .with_context(|| format!("Could not read styles file {:?}", &styles_path))?;
-            }
-
-            if let Some(base_override) = meta.get_string(&["base_font_override"]) {
-                if CONFIG.get().verbose {
-                    println!("Changing base font to {}...", base_override);
-                }
-                styles_datums = styles_datums.replace("Times New Roman", &base_override);
-            }
-            if let Some(mono_override) = meta.get_string(&["mono_font_override"]) {
-                if CONFIG.get().verbose {
-                    println!("Changing mono font to {}...", mono_override);
-                }
-                styles_datums = styles_datums.replace("Consolas", &mono_override);
-            }
-
-            fs::write(&styles_path, styles_datums)
-                .with_context(|| format!("Could not write styles file {:?}", &styles_path))?;
-        }
-
-        if CONFIG.get().verbose {
-            println!("Fixing docx metadata...");
-        }
-        let props_pkg = self
-            .get_file_root(output_path, "docProps/core.xml")
-            .with_context(|| format!("Could not get file root for {:?}", &output_path))?;
-        let props_doc = props_pkg.as_document();
-        let root = props_doc.root();
-
-        if let Some(title) = meta.get_string(&["data", "title"]) {
-            self.set_prop(&root, &factory, &context, "dc:title", &title)?;
-        }
-        if let Some(author) = meta.get_string(&["data", "author"]) {
-            self.set_prop(&root, &factory, &context, "dc:creator", &author)?;
-            self.set_prop(&root, &factory, &context, "cp:lastModifiedBy", &author)?;
-        }
-        let mut rev = meta.get_int(&["docx", "revision"]).unwrap_or(-1);
-        if rev <= 0 {
-            let git_rev_output =
-                subprocess::run_command("git", &["rev-list", "--all", "--count"], None, false)?;
-            let git_rev = git_rev_output
-                .trim()
-                .parse::<i64>()
-                .context("Could not parse revision count from git output")?;
-            rev = std::cmp::max(1, git_rev - 1);
-        }
-        self.set_prop(&root, &factory, &context, "cp:revision", &rev.to_string())?;
-
-        self.write_document(&props_doc, output_path, "docProps/core.xml")?;
+        docx_passes::run_passes(&DocxPackage::new(output_path), meta)?;
 
         let mut mod_time: Option<FileTime> = None;
         if let Ok(epoch_str) = std::env::var("SOURCE_DATE_EPOCH") {
@@ -371,43 +198,51 @@ impl Builder for DocxBuilder {
         let outfile = fs::File::create(output_file_path)
             .with_context(|| format!("Could not create file {:?}", &output_file_path))?;
         let mut zipper = zip::ZipWriter::new(outfile);
+        // fixed permissions and a UTC timestamp (rather than whatever this
+        //   machine's timezone is) so the same sources always zip the same way
         let mut options = zip::write::SimpleFileOptions::default()
-            .compression_method(zip::CompressionMethod::Deflated);
+            .compression_method(zip::CompressionMethod::Deflated)
+            .unix_permissions(0o644);
         if let Some(mt) = mod_time {
-            let offset = time::OffsetDateTime::from_unix_timestamp(mt.unix_seconds())?;
-            let now = time::OffsetDateTime::now_local()?;
-            let shifted = offset.to_offset(now.offset());
-            let zip_dt = zip::DateTime::try_from(shifted).unwrap();
+            let stamp = time::OffsetDateTime::from_unix_timestamp(mt.unix_seconds())?;
+            let zip_dt = zip::DateTime::try_from(stamp)
+                .context("Could not convert source epoch to zip timestamp")?;
             options = options.last_modified_time(zip_dt);
         }
-        let mut buffer = Vec::new();
+
+        // Word never writes directory entries, so we just do the files, in
+        //   a stable order with the content types first like everyone else
+        let mut entries = vec![];
         for entry in WalkDir::new(output_path) {
             let entry = entry.context("Invalid directory entry in walkdir")?;
-            let path = entry.path();
-            let name = path
-                .strip_prefix(output_path)
-                .context("Could not strip output prefix")?;
-
-            if path.is_file() {
-                #[allow(deprecated)]
-                zipper
-                    .start_file_from_path(name, options)
-                    .with_context(|| format!("Could not start file {:?}", name))?;
-                let mut f = fs::File::open(path)
-                    .with_context(|| format!("Could not open file {:?}", &path))?;
-                f.read_to_end(&mut buffer)
-                    .with_context(|| format!("Could not read file {:?}", &path))?;
-                zipper
-                    .write_all(&buffer)
-                    .with_context(|| format!("Could not write zipped file {:?}", &path))?;
-                buffer.clear();
-            } else if !name.as_os_str().is_empty() {
-                #[allow(deprecated)]
-                zipper
-                    .add_directory_from_path(name, options)
-                    .with_context(|| format!("Could not add directory to zip {:?}", &name))?;
+            if entry.path().is_file() {
+                let name = entry
+                    .path()
+                    .strip_prefix(output_path)
+                    .context("Could not strip output prefix")?
+                    .to_string_lossy()
+                    .replace('\\', "/");
+                entries.push((name, entry.path().to_path_buf()));
             }
         }
+        entries.sort_by(|a, b| {
+            (a.0 != CONTENT_TYPES_PART, &a.0).cmp(&(b.0 != CONTENT_TYPES_PART, &b.0))
+        });
+
+        let mut buffer = Vec::new();
+        for (name, path) in entries {
+            zipper
+                .start_file(name.as_str(), options)
+                .with_context(|| format!("Could not start file {:?}", name))?;
+            let mut f = fs::File::open(&path)
+                .with_context(|| format!("Could not open file {:?}", &path))?;
+            f.read_to_end(&mut buffer)
+                .with_context(|| format!("Could not read file {:?}", &path))?;
+            zipper
+                .write_all(&buffer)
+                .with_context(|| format!("Could not write zipped file {:?}", &path))?;
+            buffer.clear();
+        }
         zipper.finish().context("Could not finish zip file")?;
 
         Ok(vec![])
@@ -415,83 +250,56 @@ impl Builder for DocxBuilder {
 }
 
 impl DocxBuilder {
-    fn get_file_root(&self, base: &Path, path_str: &str) -> Result<sxd_document::Package> {
-        let path = base.join(path_str);
-        let pstr = path.as_os_str();
-        let mut file =
-            fs::File::open(&path).with_context(|| format!("Could not open file {:?}.", pstr))?;
-
-        let mut zbuff = String::new();
-        file.read_to_string(&mut zbuff)
-            .with_context(|| format!("Could not read file {:?}.", pstr))?;
-
-        let docx = sxd_document::parser::parse(&zbuff)
-            .with_context(|| format!("Could not parse {:?} as XML.", pstr))?;
-
-        Ok(docx)
-    }
-
-    fn write_document(
-        &self,
-        doc: &sxd_document::dom::Document,
-        base: &Path,
-        path_str: &str,
-    ) -> Result<()> {
-        let path = base.join(path_str);
-        let pstr = path.as_os_str();
-
-        let mut file = fs::File::create(&path)
-            .with_context(|| format!("Could not create file {:?}.", pstr))?;
-
-        let writer = sxd_document::writer::Writer::new().set_single_quotes(false);
-        writer
-            .format_document(doc, &mut file)
-            .context("Unable to output XML document.")?;
-        Ok(())
+    // a project's own template wins; older projects that predate the
+    //   template fall back to the installed one
+    fn get_title_page_template_path(&self) -> PathBuf {
+        let local = Path::new(".paper_resources").join(TITLE_PAGE_TEMPLATE);
+        if local.exists() {
+            return local;
+        }
+        CONFIG
+            .get()
+            .resources_path
+            .join("project_template")
+            .join(".paper_resources")
+            .join(TITLE_PAGE_TEMPLATE)
     }
 
-    fn get_xpath(&self, factory: &sxd_xpath::Factory, path: &str) -> Result<sxd_xpath::XPath> {
-        let xp = factory
-            .build(path)
-            .with_context(|| format!("Could not parse XPath: {}", path))?
-            .with_context(|| format!("Could not build XPath: {}", path))?;
-        Ok(xp)
-    }
+    // runs the title page template through pandoc's template engine,
+    //   with every `data` key available as a variable
+    fn render_title_page(&self, meta: &PaperMeta) -> Result<String> {
+        let template_path = self.get_title_page_template_path();
+        if CONFIG.get().verbose {
+            println!("Using title page template {:?}...", template_path);
+        }
 
-    fn set_prop(
-        &self,
-        root: &sxd_document::dom::Root,
-        fact: &sxd_xpath::Factory,
-        cont: &sxd_xpath::Context,
-        id: &str,
-        value: &str,
-    ) -> Result<()> {
-        let xpath_str = format!("//{}", id);
-        let xpath = self.get_xpath(fact, &xpath_str)?;
-        let val = xpath
-            .evaluate(cont, *root)
-            .context("Could not evaluate xpath")?;
-        if let Nodeset(ns) = val {
-            let el = match ns.document_order_first() {
-                Some(e) => e
-                    .element()
-                    .with_context(|| format!("Could not convert to Element with {}", id))?,
-                None => {
-                    let new_el = root.document().create_element(id);
-                    root.children()
-                        .first()
-                        .context("Core properties root has no children")?
-                        .element()
-                        .context("Core properties root has a first child that is not an Element")?
-                        .append_child(new_el);
-                    new_el
-                }
-            };
-            el.set_text(value);
-        } else {
-            bail!("XPath {} did not return Nodeset.", id);
+        let mut args = vec![
+            "--from".to_string(),
+            CONFIG.get().pandoc_input_format.clone(),
+            "--to".to_string(),
+            CONFIG.get().pandoc_input_format.clone(),
+            "--template".to_string(),
+            template_path.to_string_lossy().to_string(),
+        ];
+        let mut data = meta.data.get_variables();
+        let title_page_vars = build::get_title_page_variables(meta)?;
+        data.retain(|(k, _)| !title_page_vars.iter().any(|(tk, _)| tk == k));
+        data.extend(title_page_vars);
+        for (k, mut v) in data {
+            if k == "date" {
+                v = util::get_date_string(meta)?;
+            }
+            args.extend_from_slice(&["--variable".to_string(), format!("{}={}", k, v)]);
         }
 
-        Ok(())
+        let title_page = subprocess::run_command(
+            &pandoc_wrap::get_pandoc_exe_path()?.to_string_lossy(),
+            &args,
+            Some(""),
+            false,
+        )
+        .with_context(|| format!("Could not render title page from {:?}", template_path))?;
+
+        Ok(title_page)
     }
 }
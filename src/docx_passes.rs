@@ -0,0 +1,1855 @@
+// The fixes we make to pandoc's docx output, each as its own pass over the
+//   unzipped package. They run in the order `get_passes` lists them, and any
+//   of them can be turned off from the meta file with
+//   `docx.passes.<name>: false` if it turns out to be doing more harm than good.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{bail, Context, Result};
+use chrono::DateTime;
+use regex::Regex;
+use sxd_document::dom::{ChildOfElement, Document, Element};
+use sxd_document::QName;
+use sxd_xpath::Value::Nodeset;
+
+use crate::config::CONFIG;
+use crate::metadata::{Margins, PaperMeta};
+use crate::subprocess;
+use crate::util;
+use crate::wc;
+
+pub const DOCX_SCHEMA: &str = "http://schemas.openxmlformats.org/wordprocessingml/2006/main";
+const PROP_SCHEMA: &str = "http://schemas.openxmlformats.org/package/2006/metadata/core-properties";
+const DCMD_SCHEMA: &str = "http://purl.org/dc/elements/1.1/";
+const DCTERMS_SCHEMA: &str = "http://purl.org/dc/terms/";
+const XSI_SCHEMA: &str = "http://www.w3.org/2001/XMLSchema-instance";
+const CONTENT_TYPES_SCHEMA: &str = "http://schemas.openxmlformats.org/package/2006/content-types";
+const CUSTOM_PROPS_SCHEMA: &str =
+    "http://schemas.openxmlformats.org/officeDocument/2006/custom-properties";
+const VT_SCHEMA: &str = "http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes";
+const REL_SCHEMA: &str = "http://schemas.openxmlformats.org/officeDocument/2006/relationships";
+const PKG_REL_SCHEMA: &str = "http://schemas.openxmlformats.org/package/2006/relationships";
+const MC_SCHEMA: &str = "http://schemas.openxmlformats.org/markup-compatibility/2006";
+pub const XML_NAMESPACE: &str = "http://www.w3.org/XML/1998/namespace";
+// what the docx_annotations filter uses for its markers
+const ANNOTATION_SCHEMA: &str = "https://github.com/sjml/paper/annotations";
+
+// prefixes usable in XPaths, both ours and the ones in `docx.xpath_rules`
+const NAMESPACES: &[(&str, &str)] = &[
+    ("w", DOCX_SCHEMA),
+    ("r", REL_SCHEMA),
+    ("cp", PROP_SCHEMA),
+    ("dc", DCMD_SCHEMA),
+    ("dcterms", DCTERMS_SCHEMA),
+    ("xsi", XSI_SCHEMA),
+    ("ct", CONTENT_TYPES_SCHEMA),
+    ("pr", PKG_REL_SCHEMA),
+    ("mc", MC_SCHEMA),
+    ("xml", XML_NAMESPACE),
+    ("paper", ANNOTATION_SCHEMA),
+];
+
+pub const DOCUMENT_PART: &str = "word/document.xml";
+pub const STYLES_PART: &str = "word/styles.xml";
+pub const CONTENT_TYPES_PART: &str = "[Content_Types].xml";
+const CORE_PROPS_PART: &str = "docProps/core.xml";
+const CUSTOM_PROPS_PART: &str = "docProps/custom.xml";
+const CUSTOM_PROPS_FMTID: &str = "{D5CDD505-2E9C-101B-9397-08002B2CF9AE}";
+const CUSTOM_PROPS_SKELETON: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Properties xmlns="http://schemas.openxmlformats.org/officeDocument/2006/custom-properties" xmlns:vt="http://schemas.openxmlformats.org/officeDocument/2006/docPropsVTypes"></Properties>"#;
+const COMMENTS_PART: &str = "word/comments.xml";
+const COMMENTS_SKELETON: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:comments xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"></w:comments>"#;
+// the parts where annotations can show up
+const ANNOTATED_PARTS: &[&str] = &[DOCUMENT_PART, "word/footnotes.xml", "word/endnotes.xml"];
+
+// schema orderings for the children we might need to add; Word is picky
+//   about these even though most other readers aren't
+const STYLE_ORDER: &[&str] = &[
+    "name",
+    "aliases",
+    "basedOn",
+    "next",
+    "link",
+    "autoRedefine",
+    "hidden",
+    "uiPriority",
+    "semiHidden",
+    "unhideWhenUsed",
+    "qFormat",
+    "locked",
+    "personal",
+    "personalCompose",
+    "personalReply",
+    "rsid",
+    "pPr",
+    "rPr",
+    "tblPr",
+    "trPr",
+    "tcPr",
+    "tblStylePr",
+];
+pub const RPR_ORDER: &[&str] = &[
+    "rStyle",
+    "rFonts",
+    "b",
+    "bCs",
+    "i",
+    "iCs",
+    "caps",
+    "smallCaps",
+    "strike",
+    "dstrike",
+    "outline",
+    "shadow",
+    "emboss",
+    "imprint",
+    "noProof",
+    "snapToGrid",
+    "vanish",
+    "webHidden",
+    "color",
+    "spacing",
+    "w",
+    "kern",
+    "position",
+    "sz",
+    "szCs",
+    "highlight",
+    "u",
+    "effect",
+    "bdr",
+    "shd",
+    "fitText",
+    "vertAlign",
+    "rtl",
+    "cs",
+    "em",
+    "lang",
+    "eastAsianLayout",
+    "specVanish",
+    "oMath",
+];
+pub const PPR_ORDER: &[&str] = &[
+    "pStyle",
+    "keepNext",
+    "keepLines",
+    "pageBreakBefore",
+    "framePr",
+    "widowControl",
+    "numPr",
+    "suppressLineNumbers",
+    "pBdr",
+    "shd",
+    "tabs",
+    "suppressAutoHyphens",
+    "kinsoku",
+    "wordWrap",
+    "overflowPunct",
+    "topLinePunct",
+    "autoSpaceDE",
+    "autoSpaceDN",
+    "bidi",
+    "adjustRightInd",
+    "snapToGrid",
+    "spacing",
+    "ind",
+    "contextualSpacing",
+    "mirrorIndents",
+    "suppressOverlap",
+    "jc",
+    "textDirection",
+    "textAlignment",
+    "textboxTightWrap",
+    "outlineLvl",
+    "divId",
+    "cnfStyle",
+    "rPr",
+    "sectPr",
+    "pPrChange",
+];
+const SECT_ORDER: &[&str] = &[
+    "headerReference",
+    "footerReference",
+    "footnotePr",
+    "endnotePr",
+    "type",
+    "pgSz",
+    "pgMar",
+    "paperSrc",
+    "pgBorders",
+    "lnNumType",
+    "pgNumType",
+    "cols",
+    "formProt",
+    "vAlign",
+    "noEndnote",
+    "titlePg",
+    "textDirection",
+    "bidi",
+    "rtlGutter",
+    "docGrid",
+    "printerSettings",
+    "sectPrChange",
+];
+
+// page dimensions in twips (1/1440 inch)
+const LETTER_SIZE: (i64, i64) = (12240, 15840);
+const A4_SIZE: (i64, i64) = (11906, 16838);
+const MARGIN_SIDES: [&str; 4] = ["top", "right", "bottom", "left"];
+
+pub trait DocxPostProcessor {
+    // the key for turning this pass off under `docx.passes`
+    fn name(&self) -> &'static str;
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()>;
+}
+
+pub fn get_passes() -> Vec<Box<dyn DocxPostProcessor>> {
+    vec![
+        Box::new(Annotations),
+        Box::new(TableTotalRow),
+        Box::new(TableAlignment),
+        Box::new(PageSetup),
+        Box::new(HeaderFooter),
+        Box::new(StyleOverrides),
+        Box::new(CoreProperties),
+        Box::new(CustomProperties),
+        Box::new(XPathRules),
+        Box::new(NormalizeIds),
+        Box::new(SortContentTypes),
+    ]
+}
+
+// passes are on unless the metadata turns them off
+pub fn is_pass_enabled(meta: &PaperMeta, name: &str) -> bool {
+    meta.docx.passes.get(name).copied().unwrap_or(true)
+}
+
+pub fn run_passes(pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+    let passes = get_passes();
+
+    for configured in meta.docx.passes.keys() {
+        if !passes.iter().any(|p| p.name() == configured) {
+            eprintln!(
+                "[WARNING] Unknown docx pass `{}` in `docx.passes`.",
+                configured
+            );
+        }
+    }
+
+    for pass in passes {
+        if !is_pass_enabled(meta, pass.name()) {
+            if CONFIG.get().verbose {
+                println!("Skipping disabled docx pass `{}`...", pass.name());
+            }
+            continue;
+        }
+        pass.run(pkg, meta)
+            .with_context(|| format!("Docx pass `{}` failed", pass.name()))?;
+    }
+
+    Ok(())
+}
+
+// an unzipped docx, with the XML plumbing the passes all need
+pub struct DocxPackage<'a> {
+    base: &'a Path,
+    factory: sxd_xpath::Factory,
+    context: sxd_xpath::Context<'static>,
+}
+
+impl<'a> DocxPackage<'a> {
+    pub fn new(base: &'a Path) -> Self {
+        let mut context = sxd_xpath::Context::new();
+        for (prefix, uri) in NAMESPACES {
+            context.set_namespace(prefix, uri);
+        }
+        DocxPackage {
+            base,
+            factory: sxd_xpath::Factory::new(),
+            context,
+        }
+    }
+
+    pub fn has_part(&self, part: &str) -> bool {
+        self.base.join(part).exists()
+    }
+
+    pub fn read_part(&self, part: &str) -> Result<sxd_document::Package> {
+        let path = self.base.join(part);
+        let datums = fs::read_to_string(&path)
+            .with_context(|| format!("Could not read file {:?}.", path))?;
+        sxd_document::parser::parse(&datums)
+            .with_context(|| format!("Could not parse {:?} as XML.", path))
+    }
+
+    pub fn write_part(&self, doc: &Document, part: &str) -> Result<()> {
+        let path = self.base.join(part);
+
+        let mut buffer = Vec::new();
+        let writer = sxd_document::writer::Writer::new().set_single_quotes(false);
+        writer
+            .format_document(doc, &mut buffer)
+            .context("Unable to output XML document.")?;
+        let mut output = String::from_utf8(buffer).context("XML output was not UTF-8")?;
+
+        // sxd-document only writes out the namespace declarations that are
+        //   actually used, but Word wants every prefix listed in `mc:Ignorable`
+        //   to be declared and refuses to open the file otherwise. So carry
+        //   over whatever the original root element declared.
+        if let Ok(original) = fs::read_to_string(&path) {
+            output = restore_root_namespaces(&original, &output)?;
+        }
+        output = fix_xml_prefix(&output)?;
+
+        fs::write(&path, output).with_context(|| format!("Could not write file {:?}.", path))
+    }
+
+    pub fn select<'d>(&self, doc: &Document<'d>, path: &str) -> Result<Vec<Element<'d>>> {
+        let xpath = self
+            .factory
+            .build(path)
+            .with_context(|| format!("Could not parse XPath: {}", path))?
+            .with_context(|| format!("Could not build XPath: {}", path))?;
+        let val = xpath
+            .evaluate(&self.context, doc.root())
+            .with_context(|| format!("Could not evaluate XPath: {}", path))?;
+        match val {
+            Nodeset(ns) => Ok(ns
+                .document_order()
+                .into_iter()
+                .filter_map(|n| n.element())
+                .collect()),
+            _ => bail!("XPath {} did not return Nodeset", path),
+        }
+    }
+
+    pub fn select_string(&self, doc: &Document, path: &str) -> Result<String> {
+        let xpath = self
+            .factory
+            .build(path)
+            .with_context(|| format!("Could not parse XPath: {}", path))?
+            .with_context(|| format!("Could not build XPath: {}", path))?;
+        Ok(xpath
+            .evaluate(&self.context, doc.root())
+            .with_context(|| format!("Could not evaluate XPath: {}", path))?
+            .string())
+    }
+
+    // resolves a `prefix:name` against the namespaces we know about
+    pub fn resolve_name<'n>(&self, name: &'n str) -> Result<QName<'n>> {
+        match name.split_once(':') {
+            None => Ok(QName::new(name)),
+            Some((prefix, local)) => match NAMESPACES.iter().find(|(p, _)| *p == prefix) {
+                Some((_, uri)) => Ok(QName::with_namespace_uri(Some(uri), local)),
+                None => bail!("Unknown namespace prefix `{}` in `{}`", prefix, name),
+            },
+        }
+    }
+
+    // adds an entry to the package-level lists of parts, if it's not already there
+    pub fn register_part(&self, part_name: &str, content_type: &str, rel_type: &str) -> Result<()> {
+        self.register_content_type(part_name, content_type)?;
+        self.add_relationship("_rels/.rels", part_name, rel_type)?;
+        Ok(())
+    }
+
+    pub fn register_content_type(&self, part_name: &str, content_type: &str) -> Result<()> {
+        let types_pkg = self.read_part(CONTENT_TYPES_PART)?;
+        let types_doc = types_pkg.as_document();
+        let types_root = get_root_element(&types_doc)?;
+        let part_path = format!("/{}", part_name);
+        let registered = child_elements(types_root)
+            .iter()
+            .any(|e| e.attribute_value("PartName") == Some(part_path.as_str()));
+        if !registered {
+            let ovr = types_doc.create_element(QName::with_namespace_uri(
+                Some(CONTENT_TYPES_SCHEMA),
+                "Override",
+            ));
+            ovr.set_attribute_value("PartName", &part_path);
+            ovr.set_attribute_value("ContentType", content_type);
+            types_root.append_child(ovr);
+            self.write_part(&types_doc, CONTENT_TYPES_PART)?;
+        }
+        Ok(())
+    }
+
+    // returns the ID of the relationship to `target`, making one if needed
+    pub fn add_relationship(
+        &self,
+        rels_part: &str,
+        target: &str,
+        rel_type: &str,
+    ) -> Result<String> {
+        let rels_pkg = self.read_part(rels_part)?;
+        let rels_doc = rels_pkg.as_document();
+        let rels_root = get_root_element(&rels_doc)?;
+        let rels = child_elements(rels_root);
+        if let Some(existing) = rels
+            .iter()
+            .find(|e| e.attribute_value("Target") == Some(target))
+            .and_then(|e| e.attribute_value("Id"))
+        {
+            return Ok(existing.to_string());
+        }
+
+        let mut next_id = rels.len() + 1;
+        while rels
+            .iter()
+            .any(|e| e.attribute_value("Id") == Some(format!("rId{}", next_id).as_str()))
+        {
+            next_id += 1;
+        }
+        let rel_id = format!("rId{}", next_id);
+        let rel = rels_doc.create_element(QName::with_namespace_uri(
+            Some(PKG_REL_SCHEMA),
+            "Relationship",
+        ));
+        rel.set_attribute_value("Id", &rel_id);
+        rel.set_attribute_value("Type", rel_type);
+        rel.set_attribute_value("Target", target);
+        rels_root.append_child(rel);
+        self.write_part(&rels_doc, rels_part)?;
+
+        Ok(rel_id)
+    }
+
+    // finds the file for the default header or footer of the last section
+    fn get_default_part(&self, doc: &Document, reference: &str) -> Result<Option<String>> {
+        let sect = match self.select(doc, "//w:sectPr")?.pop() {
+            Some(s) => s,
+            None => return Ok(None),
+        };
+        let rel_id = child_elements(sect)
+            .into_iter()
+            .filter(|e| e.name() == w(reference))
+            .find(|e| e.attribute_value(w("type")) == Some("default"))
+            .and_then(|e| {
+                e.attribute_value(QName::with_namespace_uri(Some(REL_SCHEMA), "id"))
+                    .map(String::from)
+            });
+        let rel_id = match rel_id {
+            Some(id) => id,
+            None => return Ok(None),
+        };
+
+        let rels_pkg = self.read_part("word/_rels/document.xml.rels")?;
+        let target = self.select_string(
+            &rels_pkg.as_document(),
+            &format!("//pr:Relationship[@Id='{}']/@Target", rel_id),
+        )?;
+        if target.is_empty() {
+            return Ok(None);
+        }
+        Ok(Some(format!("word/{}", target)))
+    }
+}
+
+pub fn w(local: &str) -> QName<'_> {
+    QName::with_namespace_uri(Some(DOCX_SCHEMA), local)
+}
+
+// makes a new element in the main docx namespace
+pub fn new_w_element<'d>(doc: Document<'d>, local: &str) -> Element<'d> {
+    let el = doc.create_element(w(local));
+    el.set_preferred_prefix(Some("w"));
+    el
+}
+
+pub fn get_root_element<'d>(doc: &Document<'d>) -> Result<Element<'d>> {
+    doc.root()
+        .children()
+        .into_iter()
+        .find_map(|c| c.element())
+        .context("XML document has no root element")
+}
+
+pub fn child_elements(parent: Element) -> Vec<Element> {
+    parent
+        .children()
+        .into_iter()
+        .filter_map(|c| c.element())
+        .collect()
+}
+
+pub fn find_child<'d>(parent: Element<'d>, local_name: &str) -> Option<Element<'d>> {
+    child_elements(parent).into_iter().find(|e| {
+        e.name().namespace_uri() == Some(DOCX_SCHEMA) && e.name().local_part() == local_name
+    })
+}
+
+// gets the named child, making it in the right spot according to `order` if
+//   it's not there yet
+pub fn ensure_child<'d>(parent: Element<'d>, local_name: &str, order: &[&str]) -> Element<'d> {
+    if let Some(existing) = find_child(parent, local_name) {
+        return existing;
+    }
+
+    let new_el = new_w_element(parent.document(), local_name);
+
+    let rank = |name: &str| order.iter().position(|o| *o == name);
+    let new_rank = rank(local_name);
+    let mut children = parent.children();
+    let idx = children
+        .iter()
+        .position(|c| {
+            match (
+                c.element().and_then(|e| rank(e.name().local_part())),
+                new_rank,
+            ) {
+                (Some(r), Some(nr)) => r > nr,
+                _ => false,
+            }
+        })
+        .unwrap_or(children.len());
+    children.insert(idx, new_el.into());
+    // sxd has no insert-before, but replacing the children re-appends them in order
+    parent.replace_children(children);
+
+    new_el
+}
+
+// sxd can't move nodes between documents, so this makes a deep copy
+pub fn copy_element<'d>(doc: Document<'d>, source: Element) -> Element<'d> {
+    let el = doc.create_element(source.name());
+    el.set_preferred_prefix(source.preferred_prefix());
+    for attr in source.attributes() {
+        el.set_attribute_value(attr.name(), attr.value());
+    }
+    for child in source.children() {
+        match child {
+            ChildOfElement::Element(ce) => el.append_child(copy_element(doc, ce)),
+            ChildOfElement::Text(t) => el.append_child(doc.create_text(t.text())),
+            _ => {}
+        }
+    }
+    el
+}
+
+fn get_root_tag(xml: &str) -> Option<regex::Match<'_>> {
+    // the first tag that isn't the XML declaration, a comment, or a doctype
+    Regex::new(r"<[A-Za-z_][^>]*>").ok()?.find(xml)
+}
+
+// sxd also doesn't know that `xml:` is always bound, so attributes like
+//   `xml:space` on elements we make end up with a generated prefix declared
+//   for the XML namespace, which is illegal.
+fn fix_xml_prefix(output: &str) -> Result<String> {
+    let decl_search = Regex::new(&format!(
+        r#"\s+xmlns:([\w.-]+)=["']{}["']"#,
+        regex::escape(XML_NAMESPACE)
+    ))
+    .context("Could not compile regex")?;
+    let prefixes: Vec<String> = decl_search
+        .captures_iter(output)
+        .map(|c| c[1].to_string())
+        .collect();
+    if prefixes.is_empty() {
+        return Ok(output.to_string());
+    }
+
+    let mut fixed = decl_search.replace_all(output, "").to_string();
+    for prefix in prefixes {
+        let usage = Regex::new(&format!(r"(\s){}:", regex::escape(&prefix)))
+            .context("Could not compile regex")?;
+        fixed = usage.replace_all(&fixed, "${1}xml:").to_string();
+    }
+    Ok(fixed)
+}
+
+fn restore_root_namespaces(original: &str, output: &str) -> Result<String> {
+    let xmlns_search =
+        Regex::new(r#"xmlns(:[\w.-]+)?=("[^"]*"|'[^']*')"#).context("Could not compile regex")?;
+    let (orig_tag, out_tag) = match (get_root_tag(original), get_root_tag(output)) {
+        (Some(o), Some(n)) => (o, n),
+        _ => return Ok(output.to_string()),
+    };
+
+    let declared: Vec<&str> = xmlns_search
+        .captures_iter(out_tag.as_str())
+        .map(|c| c.get(1).map_or("", |m| m.as_str()))
+        .collect();
+    let missing: Vec<&str> = xmlns_search
+        .captures_iter(orig_tag.as_str())
+        .filter(|c| !declared.contains(&c.get(1).map_or("", |m| m.as_str())))
+        .map(|c| c.get(0).unwrap().as_str())
+        .collect();
+    if missing.is_empty() {
+        return Ok(output.to_string());
+    }
+
+    let tag = out_tag.as_str();
+    let insert_at = out_tag.start() + tag.len() - if tag.ends_with("/>") { 2 } else { 1 };
+    Ok(format!(
+        "{} {}{}",
+        &output[..insert_at],
+        missing.join(" "),
+        &output[insert_at..]
+    ))
+}
+
+// when the last content change was, in the format Word uses for dates
+fn get_source_date_stamp() -> Result<Option<String>> {
+    let epoch_str = match std::env::var("SOURCE_DATE_EPOCH") {
+        Ok(es) => es,
+        Err(_) => return Ok(None),
+    };
+    let epoch = epoch_str
+        .parse::<i64>()
+        .context("Could not parse epoch string into i64")?;
+    let stamp = DateTime::from_timestamp(epoch, 0)
+        .context("Source epoch is out of range")?
+        .format("%Y-%m-%dT%H:%M:%SZ")
+        .to_string();
+    Ok(Some(stamp))
+}
+
+struct ReviewComment {
+    id: String,
+    author: String,
+    date: Option<String>,
+    note: String,
+}
+
+// review notes from `.comment`, `.ins`, and `.del` spans, which the
+//   docx_annotations filter leaves as marker elements for us to turn into
+//   Word comments and tracked changes (or drop, for a submission copy)
+struct Annotations;
+
+impl Annotations {
+    // pandoc makes its own comments and changes from `.comment-start`,
+    //   `.insertion`, and `.deletion` spans, so number ours after those
+    fn get_first_free_id(&self, pkg: &DocxPackage) -> Result<i64> {
+        let mut max_id = -1;
+        for part in ANNOTATED_PARTS.iter().chain(&[COMMENTS_PART]) {
+            if !pkg.has_part(part) {
+                continue;
+            }
+            let part_pkg = pkg.read_part(part)?;
+            let part_doc = part_pkg.as_document();
+            for el in pkg.select(&part_doc, "//w:comment | //w:ins | //w:del")? {
+                if let Some(id) = el
+                    .attribute_value(w("id"))
+                    .and_then(|id| id.parse::<i64>().ok())
+                {
+                    max_id = std::cmp::max(max_id, id);
+                }
+            }
+        }
+        Ok(max_id + 1)
+    }
+
+    // tracked changes can only go around runs, so anything else in the
+    //   way (like a hyperlink) gets the runs inside it wrapped instead
+    fn wrap_runs<'d>(
+        &self,
+        nodes: Vec<ChildOfElement<'d>>,
+        make_wrapper: &mut dyn FnMut() -> Element<'d>,
+    ) -> Vec<ChildOfElement<'d>> {
+        let mut wrapped = vec![];
+        let mut group: Option<Element> = None;
+        for node in nodes {
+            match node.element() {
+                Some(el) if el.name() == w("r") => {
+                    let wrapper = *group.get_or_insert_with(|| {
+                        let wr = make_wrapper();
+                        wrapped.push(wr.into());
+                        wr
+                    });
+                    wrapper.append_child(el);
+                }
+                // already tracked, or bookkeeping like bookmarks and comment ranges
+                Some(el)
+                    if el.name() == w("ins")
+                        || el.name() == w("del")
+                        || el.children().is_empty() =>
+                {
+                    group = None;
+                    wrapped.push(node);
+                }
+                Some(el) => {
+                    group = None;
+                    let children = self.wrap_runs(el.children(), make_wrapper);
+                    el.replace_children(children);
+                    wrapped.push(node);
+                }
+                None => {
+                    group = None;
+                    wrapped.push(node);
+                }
+            }
+        }
+        wrapped
+    }
+
+    fn write_comments(&self, pkg: &DocxPackage, comments: &[ReviewComment]) -> Result<()> {
+        let existed = pkg.has_part(COMMENTS_PART);
+        let comments_pkg = if existed {
+            pkg.read_part(COMMENTS_PART)?
+        } else {
+            sxd_document::parser::parse(COMMENTS_SKELETON)
+                .context("Could not parse comments skeleton")?
+        };
+        let comments_doc = comments_pkg.as_document();
+        let comments_root = get_root_element(&comments_doc)?;
+
+        for rc in comments {
+            let comment = new_w_element(comments_doc, "comment");
+            comment.set_attribute_value(w("id"), &rc.id);
+            comment.set_attribute_value(w("author"), &rc.author);
+            let initials: String = rc
+                .author
+                .split_whitespace()
+                .filter_map(|name| name.chars().next())
+                .flat_map(|c| c.to_uppercase())
+                .collect();
+            comment.set_attribute_value(w("initials"), &initials);
+            if let Some(date) = &rc.date {
+                comment.set_attribute_value(w("date"), date);
+            }
+
+            let lines: Vec<&str> = if rc.note.is_empty() {
+                vec![""]
+            } else {
+                rc.note.lines().collect()
+            };
+            for (i, line) in lines.into_iter().enumerate() {
+                let para = new_w_element(comments_doc, "p");
+                if i == 0 {
+                    let ref_run = new_w_element(comments_doc, "r");
+                    ref_run.append_child(new_w_element(comments_doc, "annotationRef"));
+                    para.append_child(ref_run);
+                }
+                let run = new_w_element(comments_doc, "r");
+                let run_text = new_w_element(comments_doc, "t");
+                run_text.set_attribute_value(
+                    QName::with_namespace_uri(Some(XML_NAMESPACE), "space"),
+                    "preserve",
+                );
+                run_text.set_text(line);
+                run.append_child(run_text);
+                para.append_child(run);
+                comment.append_child(para);
+            }
+            comments_root.append_child(comment);
+        }
+
+        pkg.write_part(&comments_doc, COMMENTS_PART)?;
+        if !existed {
+            pkg.register_content_type(
+                COMMENTS_PART,
+                "application/vnd.openxmlformats-officedocument.wordprocessingml.comments+xml",
+            )?;
+            pkg.add_relationship(
+                "word/_rels/document.xml.rels",
+                "comments.xml",
+                "http://schemas.openxmlformats.org/officeDocument/2006/relationships/comments",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+fn rename_descendants(parent: Element, from: &str, to: &str) {
+    for child in child_elements(parent) {
+        if child.name() == w(from) {
+            child.set_name(w(to));
+        }
+        rename_descendants(child, from, to);
+    }
+}
+
+impl DocxPostProcessor for Annotations {
+    fn name(&self) -> &'static str {
+        "annotations"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        let strip = meta.docx.strip_annotations;
+        let default_author = meta
+            .data
+            .get_author_names()
+            .into_iter()
+            .next()
+            .unwrap_or_else(|| "paper".to_string());
+        let stamp = get_source_date_stamp()?;
+        let end_name = QName::with_namespace_uri(Some(ANNOTATION_SCHEMA), "end");
+
+        let mut comments = vec![];
+        let mut next_id = self.get_first_free_id(pkg)?;
+
+        for part in ANNOTATED_PARTS {
+            if !pkg.has_part(part) {
+                continue;
+            }
+            let part_pkg = pkg.read_part(part)?;
+            let part_doc = part_pkg.as_document();
+            let starts = pkg.select(&part_doc, "//paper:start")?;
+            if starts.is_empty() {
+                continue;
+            }
+            if CONFIG.get().verbose {
+                if strip {
+                    println!("Stripping {} annotation(s) from {}...", starts.len(), part);
+                } else {
+                    println!("Converting {} annotation(s) in {}...", starts.len(), part);
+                }
+            }
+
+            // innermost first, so nested annotations are already sorted out
+            //   by the time the ones around them get wrapped up
+            for start in starts.into_iter().rev() {
+                let marker_id = start.attribute_value("id").unwrap_or_default();
+                let kind = start.attribute_value("kind").unwrap_or_default();
+                let parent = start
+                    .parent()
+                    .and_then(|p| p.element())
+                    .context("Annotation marker has no parent element")?;
+                let end = start
+                    .following_siblings()
+                    .into_iter()
+                    .filter_map(|c| c.element())
+                    .find(|e| e.name() == end_name && e.attribute_value("id") == Some(marker_id));
+                let end = match end {
+                    Some(e) => e,
+                    None => {
+                        eprintln!(
+                            "[WARNING] Annotation {} in {} has no end marker; dropping it.",
+                            marker_id, part
+                        );
+                        start.remove_from_parent();
+                        continue;
+                    }
+                };
+                let between: Vec<ChildOfElement> = start
+                    .following_siblings()
+                    .into_iter()
+                    .take_while(|c| c.element() != Some(end))
+                    .collect();
+                let author = start
+                    .attribute_value("author")
+                    .map(String::from)
+                    .unwrap_or_else(|| default_author.clone());
+                let date = start
+                    .attribute_value("date")
+                    .map(String::from)
+                    .or_else(|| stamp.clone());
+
+                let replacement = match (kind, strip) {
+                    ("del", true) => vec![],
+                    (_, true) => between,
+                    ("comment", false) => {
+                        let id = next_id.to_string();
+                        next_id += 1;
+                        let range_start = new_w_element(part_doc, "commentRangeStart");
+                        range_start.set_attribute_value(w("id"), &id);
+                        let range_end = new_w_element(part_doc, "commentRangeEnd");
+                        range_end.set_attribute_value(w("id"), &id);
+                        let ref_run = new_w_element(part_doc, "r");
+                        let reference = new_w_element(part_doc, "commentReference");
+                        reference.set_attribute_value(w("id"), &id);
+                        ref_run.append_child(reference);
+                        comments.push(ReviewComment {
+                            id,
+                            author,
+                            date,
+                            note: start
+                                .attribute_value("note")
+                                .unwrap_or_default()
+                                .to_string(),
+                        });
+                        [
+                            vec![range_start.into()],
+                            between,
+                            vec![range_end.into(), ref_run.into()],
+                        ]
+                        .concat()
+                    }
+                    ("ins", false) | ("del", false) => {
+                        let mut wrappers = vec![];
+                        let mut make_wrapper = || {
+                            let change = new_w_element(part_doc, kind);
+                            change.set_attribute_value(w("id"), &next_id.to_string());
+                            next_id += 1;
+                            change.set_attribute_value(w("author"), &author);
+                            if let Some(d) = &date {
+                                change.set_attribute_value(w("date"), d);
+                            }
+                            wrappers.push(change);
+                            change
+                        };
+                        let wrapped = self.wrap_runs(between, &mut make_wrapper);
+                        // deleted text has to be marked as such, not just wrapped
+                        if kind == "del" {
+                            for wr in wrappers {
+                                rename_descendants(wr, "t", "delText");
+                                rename_descendants(wr, "instrText", "delInstrText");
+                            }
+                        }
+                        wrapped
+                    }
+                    _ => {
+                        eprintln!(
+                            "[WARNING] Unknown annotation kind `{}` in {}; leaving its text alone.",
+                            kind, part
+                        );
+                        between
+                    }
+                };
+
+                // swap the markers and everything between them for the replacement
+                let children = parent.children();
+                let start_idx = children
+                    .iter()
+                    .position(|c| c.element() == Some(start))
+                    .context("Lost track of annotation start marker")?;
+                let end_idx = children
+                    .iter()
+                    .position(|c| c.element() == Some(end))
+                    .context("Lost track of annotation end marker")?;
+                let mut spliced = children[..start_idx].to_vec();
+                spliced.extend(replacement);
+                spliced.extend_from_slice(&children[end_idx + 1..]);
+                parent.replace_children(spliced);
+            }
+
+            pkg.write_part(&part_doc, part)?;
+        }
+
+        if !comments.is_empty() {
+            self.write_comments(pkg, &comments)?;
+        }
+        Ok(())
+    }
+}
+
+// the pandoc writer doesn't check the "total row" box for tables unless
+//   they explicitly have a footer, and we use that to do the spacing since
+//   Word doesn't have a good way to add spacing after the *whole* table
+struct TableTotalRow;
+
+impl DocxPostProcessor for TableTotalRow {
+    fn name(&self) -> &'static str {
+        "table_total_row"
+    }
+
+    fn run(&self, pkg: &DocxPackage, _meta: &PaperMeta) -> Result<()> {
+        if CONFIG.get().verbose {
+            println!("Fixing docx table post-spacing styles...");
+        }
+        let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+        let doc = doc_pkg.as_document();
+        for el in pkg.select(&doc, "//w:tblLook")? {
+            el.set_attribute_value(w("lastRow"), "1");
+        }
+        pkg.write_part(&doc, DOCUMENT_PART)
+    }
+}
+
+// pandoc with version 3 started emitting a node to align all tables to the
+//   leading edge of their text.
+struct TableAlignment;
+
+impl DocxPostProcessor for TableAlignment {
+    fn name(&self) -> &'static str {
+        "table_alignment"
+    }
+
+    fn run(&self, pkg: &DocxPackage, _meta: &PaperMeta) -> Result<()> {
+        if CONFIG.get().verbose {
+            println!("Fixing docx table alignmnent...");
+        }
+        let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+        let doc = doc_pkg.as_document();
+        for el in pkg.select(&doc, "//w:tblPr/w:jc")? {
+            el.set_attribute_value(w("val"), "center");
+        }
+        pkg.write_part(&doc, DOCUMENT_PART)
+    }
+}
+
+// lengths from the meta file come in as inches; returned in twips
+fn get_twips(inches: f64) -> i64 {
+    (inches * 1440.0).round() as i64
+}
+
+struct PageSetup;
+
+impl DocxPostProcessor for PageSetup {
+    fn name(&self) -> &'static str {
+        "page_setup"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        // the meta file check only lets `letter` and `a4` through
+        let page_size = match meta.docx.paper_size.as_deref() {
+            None => None,
+            Some("a4") => Some(A4_SIZE),
+            Some(_) => Some(LETTER_SIZE),
+        };
+
+        let mut margins = vec![];
+        // either one length for all of them or a map of specific sides
+        match &meta.docx.margins {
+            None => {}
+            Some(Margins::All(all)) => {
+                let all = get_twips(*all);
+                margins = MARGIN_SIDES.iter().map(|side| (*side, all)).collect();
+            }
+            Some(Margins::Sides(sides)) => {
+                let by_side = [sides.top, sides.right, sides.bottom, sides.left];
+                for (side, length) in MARGIN_SIDES.iter().zip(by_side) {
+                    if let Some(length) = length {
+                        margins.push((*side, get_twips(length)));
+                    }
+                }
+            }
+        }
+
+        if page_size.is_none() && margins.is_empty() {
+            return Ok(());
+        }
+
+        let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+        let doc = doc_pkg.as_document();
+        for sect in pkg.select(&doc, "//w:sectPr")? {
+            if let Some((width, height)) = page_size {
+                if CONFIG.get().verbose {
+                    println!("Setting page size to {}x{} twips...", width, height);
+                }
+                let pg_sz = ensure_child(sect, "pgSz", SECT_ORDER);
+                pg_sz.set_attribute_value(w("w"), &width.to_string());
+                pg_sz.set_attribute_value(w("h"), &height.to_string());
+            }
+            if !margins.is_empty() {
+                if CONFIG.get().verbose {
+                    println!("Setting page margins...");
+                }
+                let pg_mar = ensure_child(sect, "pgMar", SECT_ORDER);
+                for (side, twips) in &margins {
+                    pg_mar.set_attribute_value(w(side), &twips.to_string());
+                }
+            }
+        }
+        pkg.write_part(&doc, DOCUMENT_PART)
+    }
+}
+
+struct HeaderFooter;
+
+impl HeaderFooter {
+    fn rewrite_header(
+        &self,
+        pkg: &DocxPackage,
+        doc: &Document,
+        text: Option<&str>,
+        number_runs: &[Element],
+    ) -> Result<()> {
+        if text.is_none() && number_runs.is_empty() {
+            return Ok(());
+        }
+        let header_path = match pkg.get_default_part(doc, "headerReference")? {
+            Some(hp) => hp,
+            None => {
+                eprintln!("[WARNING] No default header found; can't set header text.");
+                return Ok(());
+            }
+        };
+        if CONFIG.get().verbose {
+            println!("Writing running header into {}...", header_path);
+        }
+
+        let header_pkg = pkg.read_part(&header_path)?;
+        let header_doc = header_pkg.as_document();
+        let header_root = get_root_element(&header_doc)?;
+        let para = ensure_child(header_root, "p", &[]);
+        for el in child_elements(para) {
+            if el.name() != w("pPr") {
+                el.remove_from_parent();
+            }
+        }
+        let ppr = ensure_child(para, "pPr", &["pPr"]);
+        ensure_child(ppr, "jc", PPR_ORDER).set_attribute_value(w("val"), "right");
+
+        if let Some(t) = text {
+            let run = new_w_element(header_doc, "r");
+            let run_text = new_w_element(header_doc, "t");
+            run_text.set_attribute_value(
+                QName::with_namespace_uri(Some(XML_NAMESPACE), "space"),
+                "preserve",
+            );
+            if number_runs.is_empty() {
+                run_text.set_text(t);
+            } else {
+                run_text.set_text(&format!("{} ", t));
+            }
+            run.append_child(run_text);
+            para.append_child(run);
+        }
+        for nr in number_runs {
+            para.append_child(copy_element(header_doc, *nr));
+        }
+
+        pkg.write_part(&header_doc, &header_path)
+    }
+}
+
+impl DocxPostProcessor for HeaderFooter {
+    fn name(&self) -> &'static str {
+        "header_footer"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        let header_text = meta.docx.header.clone();
+        // leaving them in the footer is what the reference doc does already
+        let page_numbers = meta.docx.page_numbers.clone().filter(|pn| pn != "footer");
+        if header_text.is_none() && page_numbers.is_none() {
+            return Ok(());
+        }
+
+        let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+        let doc = doc_pkg.as_document();
+
+        // rather than building our own page number field, we lift the one
+        //   out of the reference doc's footer, since it already knows
+        //   whether to skip the title page
+        let footer_path = match page_numbers {
+            None => None,
+            Some(_) => {
+                let fp = pkg.get_default_part(&doc, "footerReference")?;
+                if fp.is_none() {
+                    eprintln!("[WARNING] No default footer found; can't move page numbers.");
+                }
+                fp
+            }
+        };
+        let footer_pkg = match &footer_path {
+            Some(fp) => Some(pkg.read_part(fp)?),
+            None => None,
+        };
+        let footer_doc = footer_pkg.as_ref().map(|fp| fp.as_document());
+
+        let mut number_runs = vec![];
+        if let Some(fd) = &footer_doc {
+            if CONFIG.get().verbose {
+                println!("Removing page numbers from the footer...");
+            }
+            for para in child_elements(get_root_element(fd)?) {
+                for run in child_elements(para) {
+                    if run.name() == w("r") {
+                        run.remove_from_parent();
+                        number_runs.push(run);
+                    }
+                }
+            }
+        }
+
+        let header_runs = match page_numbers.as_deref() {
+            Some("header") => &number_runs[..],
+            _ => &[],
+        };
+        self.rewrite_header(pkg, &doc, header_text.as_deref(), header_runs)?;
+
+        if let (Some(fp), Some(fd)) = (&footer_path, &footer_doc) {
+            pkg.write_part(fd, fp)?;
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct StyleOverride {
+    font: Option<String>,
+    size: Option<f64>,
+    line_spacing: Option<f64>,
+}
+
+// change fonts, sizes, and spacing (if needed) in the styles
+struct StyleOverrides;
+
+impl StyleOverrides {
+    // the legacy top-level overrides swap out the reference doc's fonts
+    //   wherever they're named (body text, headers, footers, hyperlinks,
+    //   ...), not just in one style
+    fn get_font_swaps(&self, meta: &PaperMeta) -> Vec<(&'static str, String)> {
+        let mut swaps = vec![];
+        if let Some(base_override) = meta.base_font_override.clone() {
+            swaps.push(("Times New Roman", base_override));
+        }
+        if let Some(mono_override) = meta.mono_font_override.clone() {
+            swaps.push(("Consolas", mono_override));
+        }
+        swaps
+    }
+
+    fn get_style_overrides(&self, meta: &PaperMeta) -> Result<Vec<(String, StyleOverride)>> {
+        let mut overrides = vec![];
+        // the main text is all Body Text (or based on it), which leaves the
+        //   footnotes, block quotes, etc. alone
+        if let Some(spacing) = meta.docx.line_spacing {
+            overrides.push((
+                "BodyText".to_string(),
+                StyleOverride {
+                    line_spacing: Some(spacing),
+                    ..Default::default()
+                },
+            ));
+        }
+        for (style, sm) in &meta.docx.styles {
+            let so = StyleOverride {
+                font: sm.font.clone(),
+                size: sm.size,
+                line_spacing: sm.line_spacing,
+            };
+            overrides.push((style.clone(), so));
+        }
+        Ok(overrides)
+    }
+
+    fn find_style<'d>(&self, styles: &[Element<'d>], style_name: &str) -> Option<Element<'d>> {
+        let squashed = style_name.replace(' ', "");
+
+        // try the ID first, then the ID-ified version of a friendly name
+        //   ("Heading 1" -> "Heading1"), then the display name itself
+        styles
+            .iter()
+            .find(|s| s.attribute_value(w("styleId")) == Some(style_name))
+            .or_else(|| {
+                styles
+                    .iter()
+                    .find(|s| s.attribute_value(w("styleId")) == Some(squashed.as_str()))
+            })
+            .or_else(|| {
+                styles.iter().find(|s| {
+                    find_child(**s, "name")
+                        .and_then(|n| n.attribute_value(w("val")))
+                        .map(|v| v.eq_ignore_ascii_case(style_name))
+                        .unwrap_or(false)
+                })
+            })
+            .copied()
+    }
+}
+
+impl DocxPostProcessor for StyleOverrides {
+    fn name(&self) -> &'static str {
+        "style_overrides"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        let swaps = self.get_font_swaps(meta);
+        let overrides = self.get_style_overrides(meta)?;
+        if swaps.is_empty() && overrides.is_empty() {
+            return Ok(());
+        }
+
+        let styles_pkg = pkg.read_part(STYLES_PART)?;
+        let styles_doc = styles_pkg.as_document();
+
+        // these go first so anything more specific in `docx.styles` wins
+        let all_fonts = pkg.select(&styles_doc, "//w:rFonts")?;
+        for (old_font, new_font) in &swaps {
+            if CONFIG.get().verbose {
+                println!("Changing {} to {}...", old_font, new_font);
+            }
+            for fonts in &all_fonts {
+                for attr in ["ascii", "hAnsi", "eastAsia", "cs"] {
+                    if fonts.attribute_value(w(attr)) == Some(*old_font) {
+                        fonts.set_attribute_value(w(attr), new_font);
+                    }
+                }
+            }
+        }
+
+        let styles = pkg.select(&styles_doc, "/w:styles/w:style")?;
+
+        for (style_name, so) in overrides {
+            let style = match self.find_style(&styles, &style_name) {
+                Some(s) => s,
+                None => {
+                    eprintln!(
+                        "[WARNING] No style `{}` in the reference doc; skipping its overrides.",
+                        style_name
+                    );
+                    continue;
+                }
+            };
+
+            if let Some(font) = &so.font {
+                if CONFIG.get().verbose {
+                    println!("Changing {} font to {}...", style_name, font);
+                }
+                let rpr = ensure_child(style, "rPr", STYLE_ORDER);
+                let fonts = ensure_child(rpr, "rFonts", RPR_ORDER);
+                for attr in ["ascii", "hAnsi", "eastAsia", "cs"] {
+                    fonts.set_attribute_value(w(attr), font);
+                }
+                // theme fonts take precedence over explicit ones, so they have to go
+                for attr in ["asciiTheme", "hAnsiTheme", "eastAsiaTheme", "cstheme"] {
+                    fonts.remove_attribute(w(attr));
+                }
+            }
+
+            if let Some(size) = so.size {
+                if CONFIG.get().verbose {
+                    println!("Changing {} size to {}pt...", style_name, size);
+                }
+                // Word measures font sizes in half-points
+                let half_points = ((size * 2.0).round() as i64).to_string();
+                let rpr = ensure_child(style, "rPr", STYLE_ORDER);
+                ensure_child(rpr, "sz", RPR_ORDER).set_attribute_value(w("val"), &half_points);
+                ensure_child(rpr, "szCs", RPR_ORDER).set_attribute_value(w("val"), &half_points);
+            }
+
+            if let Some(spacing) = so.line_spacing {
+                if CONFIG.get().verbose {
+                    println!("Changing {} line spacing to {}...", style_name, spacing);
+                }
+                // "auto" line rule is in 240ths of a line
+                let line = ((spacing * 240.0).round() as i64).to_string();
+                let ppr = ensure_child(style, "pPr", STYLE_ORDER);
+                let sp = ensure_child(ppr, "spacing", PPR_ORDER);
+                sp.set_attribute_value(w("line"), &line);
+                sp.set_attribute_value(w("lineRule"), "auto");
+            }
+        }
+
+        pkg.write_part(&styles_doc, STYLES_PART)
+    }
+}
+
+struct CoreProperties;
+
+impl CoreProperties {
+    fn set_prop<'d>(
+        &self,
+        pkg: &DocxPackage,
+        doc: &Document<'d>,
+        id: &str,
+        value: &str,
+    ) -> Result<Element<'d>> {
+        let el = match pkg.select(doc, &format!("//{}", id))?.first() {
+            Some(e) => *e,
+            None => {
+                let new_el = doc.create_element(id);
+                get_root_element(doc)
+                    .context("Core properties root has no children")?
+                    .append_child(new_el);
+                new_el
+            }
+        };
+        el.set_text(value);
+        Ok(el)
+    }
+}
+
+impl DocxPostProcessor for CoreProperties {
+    fn name(&self) -> &'static str {
+        "core_properties"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        if CONFIG.get().verbose {
+            println!("Fixing docx metadata...");
+        }
+        let props_pkg = pkg.read_part(CORE_PROPS_PART)?;
+        let props_doc = props_pkg.as_document();
+
+        if let Some(title) = &meta.data.title {
+            self.set_prop(pkg, &props_doc, "dc:title", title)?;
+        }
+        // Word itself separates multiple authors with semicolons
+        let authors = meta.data.get_author_names();
+        if let Some(first) = authors.first() {
+            self.set_prop(pkg, &props_doc, "dc:creator", &authors.join("; "))?;
+            self.set_prop(pkg, &props_doc, "cp:lastModifiedBy", first)?;
+        }
+        if let Some(class_name) = &meta.data.class_name {
+            self.set_prop(pkg, &props_doc, "dc:subject", class_name)?;
+        }
+        if !meta.data.keywords.is_empty() {
+            self.set_prop(
+                pkg,
+                &props_doc,
+                "cp:keywords",
+                &meta.data.keywords.join(", "),
+            )?;
+        }
+        if let Some(description) = &meta.data.description {
+            self.set_prop(pkg, &props_doc, "dc:description", description)?;
+        }
+        if let Some(stamp) = get_source_date_stamp()? {
+            for id in ["dcterms:created", "dcterms:modified"] {
+                let el = self.set_prop(pkg, &props_doc, id, &stamp)?;
+                el.set_attribute_value(
+                    QName::with_namespace_uri(Some(XSI_SCHEMA), "type"),
+                    "dcterms:W3CDTF",
+                )
+                .set_preferred_prefix(Some("xsi"));
+            }
+        }
+        let mut rev = meta.docx.revision.unwrap_or(-1);
+        if rev <= 0 {
+            let git_rev_output =
+                subprocess::run_command("git", &["rev-list", "--all", "--count"], None, false)?;
+            let git_rev = git_rev_output
+                .trim()
+                .parse::<i64>()
+                .context("Could not parse revision count from git output")?;
+            rev = std::cmp::max(1, git_rev - 1);
+        }
+        self.set_prop(pkg, &props_doc, "cp:revision", &rev.to_string())?;
+
+        pkg.write_part(&props_doc, CORE_PROPS_PART)
+    }
+}
+
+// custom document properties are visible in Word's Advanced Properties
+//   dialog, so this is where we stash enough to trace a submitted file
+//   back to where it came from
+struct CustomProperties;
+
+impl DocxPostProcessor for CustomProperties {
+    fn name(&self) -> &'static str {
+        "custom_properties"
+    }
+
+    fn run(&self, pkg: &DocxPackage, _meta: &PaperMeta) -> Result<()> {
+        if CONFIG.get().verbose {
+            println!("Writing custom docx properties...");
+        }
+
+        let mut props = vec![("PaperVersion", "lpwstr", util::get_paper_version_stamp())];
+        match subprocess::run_command("git", &["rev-parse", "HEAD"], None, false) {
+            Ok(commit) => props.push(("SourceCommit", "lpwstr", commit.trim().to_string())),
+            Err(_) => {
+                if CONFIG.get().verbose {
+                    println!("No git commit to record.");
+                }
+            }
+        }
+        let word_count: usize = wc::wc_data()?.iter().map(|d| d.2).sum();
+        props.push(("WordCount", "i4", word_count.to_string()));
+
+        // pandoc puts extra metadata fields in here, so keep whatever's there
+        let existed = pkg.has_part(CUSTOM_PROPS_PART);
+        let custom_pkg = if existed {
+            pkg.read_part(CUSTOM_PROPS_PART)?
+        } else {
+            sxd_document::parser::parse(CUSTOM_PROPS_SKELETON)
+                .context("Could not parse custom properties skeleton")?
+        };
+        let custom_doc = custom_pkg.as_document();
+        let custom_root = get_root_element(&custom_doc)?;
+
+        for (name, vt_type, value) in props {
+            let existing = child_elements(custom_root);
+            let prop = match existing
+                .iter()
+                .find(|e| e.attribute_value("name") == Some(name))
+            {
+                Some(p) => *p,
+                None => {
+                    // property IDs start at 2 for whatever reason
+                    let pid = existing
+                        .iter()
+                        .filter_map(|e| e.attribute_value("pid"))
+                        .filter_map(|p| p.parse::<i64>().ok())
+                        .max()
+                        .unwrap_or(1)
+                        + 1;
+                    let p = custom_doc.create_element(QName::with_namespace_uri(
+                        Some(CUSTOM_PROPS_SCHEMA),
+                        "property",
+                    ));
+                    p.set_attribute_value("fmtid", CUSTOM_PROPS_FMTID);
+                    p.set_attribute_value("pid", &pid.to_string());
+                    p.set_attribute_value("name", name);
+                    custom_root.append_child(p);
+                    p
+                }
+            };
+            let val =
+                custom_doc.create_element(QName::with_namespace_uri(Some(VT_SCHEMA), vt_type));
+            val.set_preferred_prefix(Some("vt"));
+            val.set_text(&value);
+            prop.replace_children(vec![val]);
+        }
+
+        pkg.write_part(&custom_doc, CUSTOM_PROPS_PART)?;
+        if !existed {
+            pkg.register_part(
+                CUSTOM_PROPS_PART,
+                "application/vnd.openxmlformats-officedocument.custom-properties+xml",
+                "http://schemas.openxmlformats.org/officeDocument/2006/relationships/custom-properties",
+            )?;
+        }
+        Ok(())
+    }
+}
+
+// user-supplied fixes from `docx.xpath_rules`, for when Word does something
+//   new and annoying and we want to patch it up before there's a release
+struct XPathRules;
+
+impl DocxPostProcessor for XPathRules {
+    fn name(&self) -> &'static str {
+        "xpath_rules"
+    }
+
+    fn run(&self, pkg: &DocxPackage, meta: &PaperMeta) -> Result<()> {
+        for (i, rule) in meta.docx.xpath_rules.iter().enumerate() {
+            let (xpath, attribute, value) = match (&rule.xpath, &rule.attribute, &rule.value) {
+                (Some(x), Some(a), Some(v)) => (x.as_str(), a.as_str(), v.as_str()),
+                _ => bail!(
+                    "`docx.xpath_rules` entry {} needs `xpath`, `attribute`, and `value`",
+                    i + 1
+                ),
+            };
+            let part = rule.part.as_deref().unwrap_or(DOCUMENT_PART);
+            let attr_name = pkg.resolve_name(attribute)?;
+
+            let part_pkg = pkg.read_part(part)?;
+            let part_doc = part_pkg.as_document();
+            let matches = pkg.select(&part_doc, xpath)?;
+            if CONFIG.get().verbose {
+                println!(
+                    "Setting {}=\"{}\" on {} match(es) of {} in {}...",
+                    attribute,
+                    value,
+                    matches.len(),
+                    xpath,
+                    part
+                );
+            }
+            for el in matches {
+                el.set_attribute_value(attr_name, value);
+            }
+            pkg.write_part(&part_doc, part)?;
+        }
+
+        Ok(())
+    }
+}
+
+// every element under this one, in document order
+fn descendants(parent: Element) -> Vec<Element> {
+    let mut found = vec![];
+    for child in child_elements(parent) {
+        found.push(child);
+        found.extend(descendants(child));
+    }
+    found
+}
+
+// Pandoc numbers bookmarks, notes, and relationships as it goes, and our
+//   own passes add more of them, so the same ID can end up on different
+//   things from one build to the next. This renumbers them all in the order
+//   they're used, so `--verify-reproducible` compares what's actually in the
+//   document. (Images don't need it: pandoc names them after a hash of their
+//   contents.)
+struct NormalizeIds;
+
+impl NormalizeIds {
+    // bookmark IDs have to be unique across the main document and the notes
+    fn renumber_bookmarks(&self, pkg: &DocxPackage) -> Result<()> {
+        let mut next_id = 0;
+        for part in ANNOTATED_PARTS {
+            if !pkg.has_part(part) {
+                continue;
+            }
+            let part_pkg = pkg.read_part(part)?;
+            let part_doc = part_pkg.as_document();
+            let mut ids: HashMap<String, String> = HashMap::new();
+            for el in descendants(get_root_element(&part_doc)?) {
+                if el.name() != w("bookmarkStart") && el.name() != w("bookmarkEnd") {
+                    continue;
+                }
+                if let Some(old_id) = el.attribute_value(w("id")) {
+                    let new_id = ids.entry(old_id.to_string()).or_insert_with(|| {
+                        next_id += 1;
+                        (next_id - 1).to_string()
+                    });
+                    el.set_attribute_value(w("id"), new_id);
+                }
+            }
+            pkg.write_part(&part_doc, part)?;
+        }
+        Ok(())
+    }
+
+    // `kind` is "footnote" or "endnote"
+    fn renumber_notes(&self, pkg: &DocxPackage, notes_part: &str, kind: &str) -> Result<()> {
+        if !pkg.has_part(notes_part) {
+            return Ok(());
+        }
+        let notes_pkg = pkg.read_part(notes_part)?;
+        let notes_doc = notes_pkg.as_document();
+        let notes_root = get_root_element(&notes_doc)?;
+        let notes = child_elements(notes_root);
+
+        // the separators (which have a type) keep their IDs; the real
+        //   notes go after them
+        let (fixed, numbered): (Vec<Element>, Vec<Element>) = notes
+            .into_iter()
+            .partition(|n| n.name() != w(kind) || n.attribute_value(w("type")).is_some());
+        let mut next_id = fixed
+            .iter()
+            .filter_map(|n| n.attribute_value(w("id")))
+            .filter_map(|id| id.parse::<i64>().ok())
+            .max()
+            .map_or(1, |m| (m + 1).max(1));
+
+        let doc_pkg = pkg.read_part(DOCUMENT_PART)?;
+        let doc = doc_pkg.as_document();
+        let mut ids: HashMap<String, i64> = HashMap::new();
+        let reference_name = format!("{}Reference", kind);
+        let reference = w(&reference_name);
+        for el in descendants(get_root_element(&doc)?) {
+            if el.name() != reference {
+                continue;
+            }
+            if let Some(old_id) = el.attribute_value(w("id")) {
+                let new_id = *ids.entry(old_id.to_string()).or_insert_with(|| {
+                    next_id += 1;
+                    next_id - 1
+                });
+                el.set_attribute_value(w("id"), &new_id.to_string());
+            }
+        }
+        pkg.write_part(&doc, DOCUMENT_PART)?;
+
+        let mut renumbered = vec![];
+        for note in numbered {
+            let old_id = note.attribute_value(w("id")).unwrap_or_default();
+            let new_id = match ids.get(old_id) {
+                Some(id) => *id,
+                // nothing points at it, but it still needs an ID of its own
+                None => {
+                    next_id += 1;
+                    next_id - 1
+                }
+            };
+            note.set_attribute_value(w("id"), &new_id.to_string());
+            renumbered.push((new_id, note));
+        }
+        renumbered.sort_by_key(|(id, _)| *id);
+        let mut children = fixed;
+        children.extend(renumbered.into_iter().map(|(_, n)| n));
+        notes_root.replace_children(children);
+        pkg.write_part(&notes_doc, notes_part)
+    }
+
+    // relationship IDs are only unique within the part they belong to, so
+    //   each part's get renumbered on their own: the ones it uses in order,
+    //   then the rest (styles, settings, ...) sorted by what they are
+    fn renumber_relationships(&self, pkg: &DocxPackage, part: &str) -> Result<()> {
+        let (dir, file) = part.rsplit_once('/').unwrap_or(("", part));
+        let rels_part = format!("{}/_rels/{}.rels", dir, file);
+        if !pkg.has_part(&rels_part) {
+            return Ok(());
+        }
+        let rels_pkg = pkg.read_part(&rels_part)?;
+        let rels_doc = rels_pkg.as_document();
+        let rels_root = get_root_element(&rels_doc)?;
+        let mut rels = child_elements(rels_root);
+
+        let part_pkg = pkg.read_part(part)?;
+        let part_doc = part_pkg.as_document();
+        let mut used: Vec<String> = vec![];
+        let mut references = vec![];
+        for el in descendants(get_root_element(&part_doc)?) {
+            for attr in el.attributes() {
+                if attr.name().namespace_uri() != Some(REL_SCHEMA) {
+                    continue;
+                }
+                if !used.iter().any(|u| u == attr.value()) {
+                    used.push(attr.value().to_string());
+                }
+                references.push((el, attr.name().local_part().to_string()));
+            }
+        }
+
+        rels.sort_by_key(|r| {
+            let id = r.attribute_value("Id").unwrap_or_default();
+            (
+                used.iter().position(|u| u == id).unwrap_or(used.len()),
+                r.attribute_value("Type").unwrap_or_default().to_string(),
+                r.attribute_value("Target").unwrap_or_default().to_string(),
+            )
+        });
+        let mut ids: HashMap<String, String> = HashMap::new();
+        for (i, rel) in rels.iter().enumerate() {
+            let new_id = format!("rId{}", i + 1);
+            if let Some(old_id) = rel.attribute_value("Id") {
+                ids.insert(old_id.to_string(), new_id.clone());
+            }
+            rel.set_attribute_value("Id", &new_id);
+        }
+        rels_root.replace_children(rels);
+
+        for (el, local) in references {
+            let name = QName::with_namespace_uri(Some(REL_SCHEMA), &local);
+            if let Some(new_id) = el.attribute_value(name).and_then(|old| ids.get(old)) {
+                el.set_attribute_value(name, new_id);
+            }
+        }
+
+        pkg.write_part(&part_doc, part)?;
+        pkg.write_part(&rels_doc, &rels_part)
+    }
+}
+
+impl DocxPostProcessor for NormalizeIds {
+    fn name(&self) -> &'static str {
+        "normalize_ids"
+    }
+
+    fn run(&self, pkg: &DocxPackage, _meta: &PaperMeta) -> Result<()> {
+        self.renumber_bookmarks(pkg)?;
+        self.renumber_notes(pkg, "word/footnotes.xml", "footnote")?;
+        self.renumber_notes(pkg, "word/endnotes.xml", "endnote")?;
+
+        let rels_dir = pkg.base.join("word").join("_rels");
+        let mut parts = vec![];
+        if rels_dir.is_dir() {
+            for entry in fs::read_dir(&rels_dir)
+                .with_context(|| format!("Could not read directory {:?}", rels_dir))?
+            {
+                let entry = entry.with_context(|| format!("Could not read {:?}", rels_dir))?;
+                let name = entry.file_name().to_string_lossy().to_string();
+                if let Some(part_file) = name.strip_suffix(".rels") {
+                    parts.push(format!("word/{}", part_file));
+                }
+            }
+        }
+        parts.sort();
+        for part in parts {
+            if pkg.has_part(&part) {
+                self.renumber_relationships(pkg, &part)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// pandoc (and we) add content types in whatever order parts show up,
+//   so put them in a canonical one
+struct SortContentTypes;
+
+impl DocxPostProcessor for SortContentTypes {
+    fn name(&self) -> &'static str {
+        "sort_content_types"
+    }
+
+    fn run(&self, pkg: &DocxPackage, _meta: &PaperMeta) -> Result<()> {
+        let types_pkg = pkg.read_part(CONTENT_TYPES_PART)?;
+        let types_doc = types_pkg.as_document();
+        let types_root = get_root_element(&types_doc)?;
+
+        let mut types = child_elements(types_root);
+        types.sort_by_key(|e| {
+            let key = e
+                .attribute_value("Extension")
+                .or(e.attribute_value("PartName"))
+                .unwrap_or_default()
+                .to_string();
+            (e.name().local_part() != "Default", key)
+        });
+        types_root.replace_children(types);
+
+        pkg.write_part(&types_doc, CONTENT_TYPES_PART)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DOCUMENT: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:document xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main" xmlns:r="http://schemas.openxmlformats.org/officeDocument/2006/relationships" xmlns:a="http://schemas.openxmlformats.org/drawingml/2006/main"><w:body><w:p><w:bookmarkStart w:id="7" w:name="one"/><w:bookmarkEnd w:id="7"/><w:bookmarkStart w:id="3" w:name="two"/><w:bookmarkEnd w:id="3"/><w:r><w:footnoteReference w:id="5"/></w:r><w:hyperlink r:id="rId9"><w:r><w:t>link</w:t></w:r></w:hyperlink><w:r><w:footnoteReference w:id="2"/></w:r><w:r><w:drawing><a:blip r:embed="rId4"/></w:drawing></w:r></w:p></w:body></w:document>"#;
+    const RELS: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<Relationships xmlns="http://schemas.openxmlformats.org/package/2006/relationships"><Relationship Id="rId1" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/styles" Target="styles.xml"/><Relationship Id="rId2" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/footnotes" Target="footnotes.xml"/><Relationship Id="rId4" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/image" Target="media/abc.png"/><Relationship Id="rId9" Type="http://schemas.openxmlformats.org/officeDocument/2006/relationships/hyperlink" Target="https://example.com" TargetMode="External"/></Relationships>"#;
+    const FOOTNOTES: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes"?>
+<w:footnotes xmlns:w="http://schemas.openxmlformats.org/wordprocessingml/2006/main"><w:footnote w:type="separator" w:id="-1"/><w:footnote w:type="continuationSeparator" w:id="0"/><w:footnote w:id="2"><w:p><w:r><w:t>second</w:t></w:r></w:p></w:footnote><w:footnote w:id="5"><w:p><w:r><w:t>first</w:t></w:r></w:p></w:footnote></w:footnotes>"#;
+
+    fn attribute_values(pkg: &DocxPackage, part: &str, path: &str) -> Vec<String> {
+        let part_pkg = pkg.read_part(part).unwrap();
+        let doc = part_pkg.as_document();
+        let xpath = pkg.factory.build(path).unwrap().unwrap();
+        match xpath.evaluate(&pkg.context, doc.root()).unwrap() {
+            Nodeset(ns) => ns
+                .document_order()
+                .into_iter()
+                .map(|n| n.string_value())
+                .collect(),
+            _ => panic!("not a nodeset"),
+        }
+    }
+
+    #[test]
+    fn normalize_ids_renumbers_in_order_of_use() {
+        let dir = tempfile::tempdir().unwrap();
+        fs::create_dir_all(dir.path().join("word").join("_rels")).unwrap();
+        fs::write(dir.path().join(DOCUMENT_PART), DOCUMENT).unwrap();
+        fs::write(dir.path().join("word/_rels/document.xml.rels"), RELS).unwrap();
+        fs::write(dir.path().join("word/footnotes.xml"), FOOTNOTES).unwrap();
+        let pkg = DocxPackage::new(dir.path());
+
+        NormalizeIds.run(&pkg, &PaperMeta::default()).unwrap();
+
+        assert_eq!(
+            attribute_values(&pkg, DOCUMENT_PART, "//w:bookmarkStart/@w:id"),
+            ["0", "1"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, DOCUMENT_PART, "//w:bookmarkEnd/@w:id"),
+            ["0", "1"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, DOCUMENT_PART, "//w:footnoteReference/@w:id"),
+            ["1", "2"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, "word/footnotes.xml", "//w:footnote/@w:id"),
+            ["-1", "0", "1", "2"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, "word/footnotes.xml", "//w:footnote[@w:id='1']//w:t"),
+            ["first"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, DOCUMENT_PART, "//w:hyperlink/@r:id"),
+            ["rId1"]
+        );
+        assert_eq!(
+            attribute_values(&pkg, DOCUMENT_PART, "//*[local-name()='blip']/@r:embed"),
+            ["rId2"]
+        );
+        assert_eq!(
+            attribute_values(
+                &pkg,
+                "word/_rels/document.xml.rels",
+                "//pr:Relationship/@Target"
+            ),
+            [
+                "https://example.com",
+                "media/abc.png",
+                "footnotes.xml",
+                "styles.xml"
+            ]
+        );
+        assert_eq!(
+            attribute_values(
+                &pkg,
+                "word/_rels/document.xml.rels",
+                "//pr:Relationship/@Id"
+            ),
+            ["rId1", "rId2", "rId3", "rId4"]
+        );
+    }
+}
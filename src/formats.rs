@@ -1,25 +1,30 @@
 use std::error::Error;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::Result;
 
 use crate::metadata::PaperMeta;
 
+// what `default_format` in the meta file can be
+pub const OUTPUT_FORMATS: &[&str] = &["docx", "latex", "latex+pdf", "html", "json"];
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum OutputFormat {
     Docx,
     LaTeX,
     LaTeXPdf,
+    Html,
     Json,
 }
 
-impl ToString for OutputFormat {
-    fn to_string(&self) -> String {
+impl std::fmt::Display for OutputFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
         match self {
-            OutputFormat::Docx => "docx".to_string(),
-            OutputFormat::LaTeX => "latex".to_string(),
-            OutputFormat::LaTeXPdf => "latex+pdf".to_string(),
-            OutputFormat::Json => "json".to_string(),
+            OutputFormat::Docx => write!(f, "docx"),
+            OutputFormat::LaTeX => write!(f, "latex"),
+            OutputFormat::LaTeXPdf => write!(f, "latex+pdf"),
+            OutputFormat::Html => write!(f, "html"),
+            OutputFormat::Json => write!(f, "json"),
         }
     }
 }
@@ -54,6 +59,7 @@ impl std::str::FromStr for OutputFormat {
             "docx" => Ok(OutputFormat::Docx),
             "latex" => Ok(OutputFormat::LaTeX),
             "latex+pdf" => Ok(OutputFormat::LaTeXPdf),
+            "html" => Ok(OutputFormat::Html),
             "json" => Ok(OutputFormat::Json),
             _ => Err(ParseFormatError {}),
         }
@@ -64,5 +70,10 @@ pub trait Builder {
     fn prepare(&mut self, args: &mut Vec<String>, meta: &PaperMeta) -> Result<()>;
     fn get_file_list(&self) -> Vec<String>;
     fn get_output_file_suffix(&self) -> String;
+    // where pandoc should write, for builders that turn its output into
+    //   something else before it lands in the output directory
+    fn get_pandoc_output_path(&self, output_file_path: &Path) -> Result<PathBuf> {
+        Ok(output_file_path.to_path_buf())
+    }
     fn finish_file(&self, output_file_path: &Path, meta: &PaperMeta) -> Result<Vec<String>>;
 }
@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use anyhow::Result;
+
+use crate::build;
+use crate::config::CONFIG;
+use crate::formats::Builder;
+use crate::metadata::PaperMeta;
+
+#[derive(Default)]
+pub struct HtmlBuilder {}
+
+impl Builder for HtmlBuilder {
+    fn get_output_file_suffix(&self) -> String {
+        "html".to_string()
+    }
+
+    fn prepare(&mut self, args: &mut Vec<String>, meta: &PaperMeta) -> Result<()> {
+        let cmds = [
+            "--to=html5".to_string(),
+            "--standalone".to_string(),
+            // single file, so the preview server doesn't have to go hunting for images
+            "--embed-resources".to_string(),
+        ];
+        args.extend_from_slice(&cmds);
+
+        if let Some(title) = &meta.data.title {
+            args.extend_from_slice(&["--metadata".to_string(), format!("title={}", title)]);
+        } else {
+            args.extend_from_slice(&[
+                "--metadata".to_string(),
+                "pagetitle=Paper Preview".to_string(),
+            ]);
+        }
+        if let Some(subtitle) = &meta.data.subtitle {
+            args.extend_from_slice(&["--metadata".to_string(), format!("subtitle={}", subtitle)]);
+        }
+        // repeating it makes a list, which the HTML template knows what to
+        //   do with
+        for author in meta.data.get_author_names() {
+            args.extend_from_slice(&["--metadata".to_string(), format!("author={}", author)]);
+        }
+
+        Ok(())
+    }
+
+    fn get_file_list(&self) -> Vec<String> {
+        build::get_content_file_list()
+    }
+
+    fn finish_file(&self, _output_file_path: &Path, _meta: &PaperMeta) -> Result<Vec<String>> {
+        if CONFIG.get().verbose {
+            println!("Packaging HTML...");
+        }
+
+        // no-op
+        Ok(vec![])
+    }
+}
@@ -0,0 +1,563 @@
+// Bringing an existing document into a fresh project: the text goes into
+//   `content`, images into `content/images`, whatever metadata we can find
+//   into the meta file, and any citations we can make sense of into a
+//   starter bibliography.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context, Result};
+use regex::Regex;
+use serde_json::{json, Value};
+use yaml_rust::{yaml, Yaml};
+
+use crate::config::CONFIG;
+use crate::docx;
+use crate::docx_passes::DocxPackage;
+use crate::pandoc_wrap;
+use crate::refs;
+use crate::subprocess;
+use crate::util;
+
+const IMAGE_DIR: &str = "images";
+// LaTeX lets you leave these off of `\includegraphics`
+const IMAGE_EXTENSIONS: &[&str] = &["pdf", "png", "jpg", "jpeg", "eps"];
+
+enum SourceFormat {
+    Docx,
+    LaTeX,
+}
+
+// returns the meta values pulled from the document, to be merged over the
+//   template's
+pub fn import_document(source: &Path) -> Result<yaml::Hash> {
+    let format = match source
+        .extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .as_deref()
+    {
+        Some("docx") => SourceFormat::Docx,
+        Some("tex") | Some("latex") => SourceFormat::LaTeX,
+        _ => bail!("Can only import from .docx or .tex files, not {:?}", source),
+    };
+    let source_dir = source.parent().unwrap_or(Path::new("."));
+
+    println!("Importing {:?}...", source);
+    let media_dir = tempfile::tempdir().context("Could not create temporary directory")?;
+    let args = vec![
+        "--from".to_string(),
+        match format {
+            SourceFormat::Docx => "docx".to_string(),
+            SourceFormat::LaTeX => "latex".to_string(),
+        },
+        "--to".to_string(),
+        CONFIG.get().pandoc_input_format.clone(),
+        "--wrap".to_string(),
+        "preserve".to_string(),
+        "--extract-media".to_string(),
+        media_dir.path().to_string_lossy().to_string(),
+        source.to_string_lossy().to_string(),
+    ];
+    let markdown = subprocess::run_command(
+        &pandoc_wrap::get_pandoc_exe_path()?.to_string_lossy(),
+        &args,
+        None,
+        false,
+    )
+    .with_context(|| format!("Could not convert {:?} to Markdown", source))?;
+
+    let content_dir = Path::new(&CONFIG.get().content_directory_name);
+    let (markdown, image_count) = collect_images(&markdown, source_dir, content_dir)?;
+    let content_path = content_dir.join("index.md");
+    fs::write(&content_path, &markdown)
+        .with_context(|| format!("Could not write {:?}", content_path))?;
+
+    let (data, mut references) = match format {
+        SourceFormat::Docx => {
+            let unzip_dir = tempfile::tempdir().context("Could not create temporary directory")?;
+            docx::unzip_docx(source, unzip_dir.path())?;
+            let pkg = DocxPackage::new(unzip_dir.path());
+            (get_docx_data(&pkg)?, get_zotero_references(&pkg)?)
+        }
+        SourceFormat::LaTeX => {
+            let tex = fs::read_to_string(source)
+                .with_context(|| format!("Could not read {:?}", source))?;
+            (get_latex_data(&tex), vec![])
+        }
+    };
+    references.extend(get_footnote_references(&markdown)?);
+    let references = assign_reference_ids(references);
+
+    let mut meta = yaml::Hash::new();
+    if !data.is_empty() {
+        let mut data_hash = yaml::Hash::new();
+        for (k, v) in data {
+            data_hash.insert(Yaml::String(k), v);
+        }
+        meta.insert(Yaml::String("data".to_string()), Yaml::Hash(data_hash));
+    }
+    if !references.is_empty() {
+        let bib_path = refs::DEFAULT_PROJECT_BIBLIOGRAPHY;
+        let bib = serde_json::to_string_pretty(&Value::Array(references.clone()))
+            .context("Could not make pretty string from reference JSON")?;
+        fs::write(bib_path, bib).with_context(|| format!("Could not write {:?}", bib_path))?;
+        meta.insert(
+            Yaml::String("sources".to_string()),
+            Yaml::Array(vec![Yaml::String(bib_path.to_string())]),
+        );
+    }
+
+    println!(
+        "Imported into {:?} with {} image(s) and {} reference(s).",
+        content_path,
+        image_count,
+        references.len()
+    );
+    if !references.is_empty() {
+        println!("The footnotes are still written out by hand; swap in citation keys from `references.json` as you go.");
+    }
+
+    Ok(meta)
+}
+
+// copies every local image the Markdown points at (either extracted by
+//   pandoc or sitting next to a .tex file) into the content directory and
+//   fixes up the links
+fn collect_images(
+    markdown: &str,
+    source_dir: &Path,
+    content_dir: &Path,
+) -> Result<(String, usize)> {
+    let image_search =
+        Regex::new(r"!\[[^\]]*\]\(<?([^)>\s]+)>?").context("Could not compile regex")?;
+    let image_dir = content_dir.join(IMAGE_DIR);
+
+    let mut result = markdown.to_string();
+    let mut seen = HashSet::new();
+    let mut used_names = HashSet::new();
+    for cap in image_search.captures_iter(markdown) {
+        let link = cap[1].to_string();
+        if !seen.insert(link.clone()) {
+            continue;
+        }
+        let found = match find_image(&link, source_dir) {
+            Some(f) => f,
+            None => {
+                eprintln!(
+                    "[WARNING] Couldn't find image `{}`; leaving the link as-is.",
+                    link
+                );
+                continue;
+            }
+        };
+
+        let file_name = found
+            .file_name()
+            .map(|f| f.to_string_lossy().to_string())
+            .context("Image path has no file name")?;
+        let mut name = file_name.clone();
+        let mut n = 2;
+        while !used_names.insert(name.clone()) {
+            name = format!("{}-{}", n, file_name);
+            n += 1;
+        }
+
+        if !image_dir.exists() {
+            fs::create_dir_all(&image_dir)
+                .with_context(|| format!("Could not create {:?}", image_dir))?;
+        }
+        fs::copy(&found, image_dir.join(&name))
+            .with_context(|| format!("Could not copy image {:?}", found))?;
+
+        let new_link = format!("{}/{}", IMAGE_DIR, name);
+        result = result
+            .replace(&format!("](<{}>", link), &format!("]({}", new_link))
+            .replace(&format!("]({}", link), &format!("]({}", new_link));
+    }
+
+    Ok((result, used_names.len()))
+}
+
+fn find_image(link: &str, source_dir: &Path) -> Option<PathBuf> {
+    let path = Path::new(link);
+    let base = if path.is_absolute() {
+        path.to_path_buf()
+    } else {
+        source_dir.join(path)
+    };
+    if base.is_file() {
+        return Some(base);
+    }
+    if base.extension().is_none() {
+        for ext in IMAGE_EXTENSIONS {
+            let with_ext = base.with_extension(ext);
+            if with_ext.is_file() {
+                return Some(with_ext);
+            }
+        }
+    }
+    None
+}
+
+// the inverse of what the docx core_properties pass writes
+fn get_docx_data(pkg: &DocxPackage) -> Result<Vec<(String, Yaml)>> {
+    let mut data = vec![];
+    if !pkg.has_part("docProps/core.xml") {
+        return Ok(data);
+    }
+    let props_pkg = pkg.read_part("docProps/core.xml")?;
+    let props_doc = props_pkg.as_document();
+
+    for (key, xpath) in [
+        ("title", "//dc:title"),
+        ("author", "//dc:creator"),
+        ("class_name", "//dc:subject"),
+        ("description", "//dc:description"),
+    ] {
+        let value = pkg.select_string(&props_doc, xpath)?;
+        if !value.trim().is_empty() {
+            data.push((key.to_string(), Yaml::String(value.trim().to_string())));
+        }
+    }
+    let keywords: Vec<Yaml> = pkg
+        .select_string(&props_doc, "//cp:keywords")?
+        .split([',', ';'])
+        .map(|k| k.trim())
+        .filter(|k| !k.is_empty())
+        .map(|k| Yaml::String(k.to_string()))
+        .collect();
+    if !keywords.is_empty() {
+        data.push(("keywords".to_string(), Yaml::Array(keywords)));
+    }
+
+    Ok(data)
+}
+
+// the contents of the first `\command{...}`, respecting nested braces
+fn get_latex_argument(tex: &str, command: &str) -> Option<String> {
+    let start_search = Regex::new(&format!(r"\\{}\s*(\[[^\]]*\])?\s*\{{", command)).ok()?;
+    let start = start_search.find(tex)?.end();
+    let mut depth = 1;
+    for (i, c) in tex[start..].char_indices() {
+        match c {
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(tex[start..start + i].to_string());
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// good enough for titles and names; anything fancier than some emphasis
+//   and line breaks can get fixed by hand
+fn clean_latex(text: &str) -> String {
+    let mut cleaned = text.replace("\\\\", " ").replace("\\and", ", ");
+    while let Some(thanks) = get_latex_argument(&cleaned, "thanks") {
+        cleaned = cleaned.replace(&format!("\\thanks{{{}}}", thanks), "");
+    }
+    if let Ok(command_search) = Regex::new(r"\\[a-zA-Z]+\*?") {
+        cleaned = command_search.replace_all(&cleaned, "").to_string();
+    }
+    cleaned = cleaned.replace(['{', '}', '~'], " ");
+    cleaned
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .replace(" ,", ",")
+}
+
+fn get_latex_data(tex: &str) -> Vec<(String, Yaml)> {
+    let mut data = vec![];
+    for (key, command) in [
+        ("title", "title"),
+        ("subtitle", "subtitle"),
+        ("author", "author"),
+    ] {
+        if let Some(arg) = get_latex_argument(tex, command) {
+            let value = clean_latex(&arg);
+            if !value.is_empty() {
+                data.push((key.to_string(), Yaml::String(value)));
+            }
+        }
+    }
+    // anything `due_date` would take, so `\date{2024}` makes it through too
+    if let Some(date) = get_latex_argument(tex, "date").map(|d| clean_latex(&d)) {
+        if util::parse_due_date(&date).is_ok() {
+            data.push(("date".to_string(), Yaml::String(date)));
+        }
+    }
+    data
+}
+
+// Zotero's Word plugin stashes the full CSL JSON for each citation in its
+//   field codes, which is as good as it gets
+fn get_zotero_references(pkg: &DocxPackage) -> Result<Vec<Value>> {
+    let mut references = vec![];
+    for part in [
+        "word/document.xml",
+        "word/footnotes.xml",
+        "word/endnotes.xml",
+    ] {
+        if !pkg.has_part(part) {
+            continue;
+        }
+        let part_pkg = pkg.read_part(part)?;
+        let part_doc = part_pkg.as_document();
+        // field codes get split across runs willy-nilly
+        let instructions: String = pkg
+            .select(&part_doc, "//w:instrText")?
+            .into_iter()
+            .flat_map(|el| el.children())
+            .filter_map(|c| c.text())
+            .map(|t| t.text().to_string())
+            .collect();
+
+        for (pos, _) in instructions.match_indices("CSL_CITATION") {
+            let rest = &instructions[pos + "CSL_CITATION".len()..];
+            let json_start = match rest.find('{') {
+                Some(js) => js,
+                None => continue,
+            };
+            let citation = serde_json::Deserializer::from_str(&rest[json_start..])
+                .into_iter::<Value>()
+                .next();
+            if let Some(Ok(citation)) = citation {
+                if let Some(Value::Array(items)) = citation.get("citationItems") {
+                    references.extend(items.iter().filter_map(|i| i.get("itemData")).cloned());
+                }
+            }
+        }
+    }
+    if CONFIG.get().verbose && !references.is_empty() {
+        println!("Found {} Zotero citation(s).", references.len());
+    }
+    Ok(references)
+}
+
+fn get_csl_names(authors: &str) -> Value {
+    let names: Vec<Value> = authors
+        .split(" and ")
+        .flat_map(|a| a.split(", "))
+        .map(|a| a.trim())
+        .filter(|a| !a.is_empty())
+        .map(|a| match a.rsplit_once(' ') {
+            Some((given, family)) => json!({"given": given, "family": family}),
+            None => json!({"literal": a}),
+        })
+        .collect();
+    Value::Array(names)
+}
+
+// pulls full Chicago-style notes for books and articles out of the
+//   footnotes; short notes and ibids don't have enough to go on, so they
+//   just get skipped
+fn get_footnote_references(markdown: &str) -> Result<Vec<Value>> {
+    let note_start = Regex::new(r"^\[\^[^\]]+\]:\s*(.*)$").context("Could not compile regex")?;
+    let mut notes: Vec<String> = vec![];
+    let mut in_note = false;
+    for line in markdown.lines() {
+        if let Some(cap) = note_start.captures(line) {
+            notes.push(cap[1].to_string());
+            in_note = true;
+        } else if in_note && line.starts_with(char::is_whitespace) && !line.trim().is_empty() {
+            if let Some(n) = notes.last_mut() {
+                n.push(' ');
+                n.push_str(line.trim());
+            }
+        } else if !line.trim().is_empty() {
+            in_note = false;
+        }
+    }
+
+    let book_search = Regex::new(
+        r#"^(?P<author>[^,*"“]+?), \*(?P<title>[^*]+)\*[^(]*\((?:(?P<place>[^:()]+): )?(?P<publisher>[^()]*?),? (?P<year>\d{4})\)"#,
+    )
+    .context("Could not compile regex")?;
+    let article_search = Regex::new(
+        r#"^(?P<author>[^,*"“]+?), ["“](?P<title>[^"”]+?),?["”] \*(?P<journal>[^*]+)\*(?P<volume> [^(]*?)?\s*\([^()]*?(?P<year>\d{4})\)"#,
+    )
+    .context("Could not compile regex")?;
+
+    let mut references = vec![];
+    for note in notes {
+        for citation in note.split("; ") {
+            let citation = citation.trim();
+            if let Some(cap) = book_search.captures(citation) {
+                let mut reference = json!({
+                    "type": "book",
+                    "author": get_csl_names(&cap["author"]),
+                    "title": cap["title"].trim(),
+                    "issued": {"date-parts": [[cap["year"].parse::<i64>().unwrap_or_default()]]},
+                });
+                if let Some(place) = cap.name("place") {
+                    reference["publisher-place"] = json!(place.as_str().trim());
+                }
+                if !cap["publisher"].trim().is_empty() {
+                    reference["publisher"] = json!(cap["publisher"].trim());
+                }
+                references.push(reference);
+            } else if let Some(cap) = article_search.captures(citation) {
+                let mut reference = json!({
+                    "type": "article-journal",
+                    "author": get_csl_names(&cap["author"]),
+                    "title": cap["title"].trim(),
+                    "container-title": cap["journal"].trim(),
+                    "issued": {"date-parts": [[cap["year"].parse::<i64>().unwrap_or_default()]]},
+                });
+                if let Some(volume) = cap.name("volume") {
+                    let volume = volume.as_str().trim().trim_end_matches(',');
+                    if let Some((vol, issue)) = volume.split_once(", no. ") {
+                        reference["volume"] = json!(vol.trim());
+                        reference["issue"] = json!(issue.trim());
+                    } else if !volume.is_empty() {
+                        reference["volume"] = json!(volume);
+                    }
+                }
+                references.push(reference);
+            }
+        }
+    }
+    Ok(references)
+}
+
+// gives everything a citation key like `smith2001` and drops duplicates
+fn assign_reference_ids(references: Vec<Value>) -> Vec<Value> {
+    let mut seen_titles = HashSet::new();
+    let mut used_ids = HashSet::new();
+    let mut result = vec![];
+    for mut reference in references {
+        let title = reference
+            .get("title")
+            .and_then(|t| t.as_str())
+            .unwrap_or_default()
+            .to_lowercase();
+        if title.is_empty() || !seen_titles.insert(title) {
+            continue;
+        }
+
+        let family = reference
+            .pointer("/author/0/family")
+            .or_else(|| reference.pointer("/author/0/literal"))
+            .and_then(|f| f.as_str())
+            .unwrap_or("anon")
+            .to_lowercase()
+            .chars()
+            .filter(|c| c.is_alphanumeric())
+            .collect::<String>();
+        let year = reference
+            .pointer("/issued/date-parts/0/0")
+            .map(|y| match y {
+                Value::String(s) => s.clone(),
+                other => other.to_string(),
+            })
+            .unwrap_or_default();
+        let base = format!("{}{}", family, year);
+        let mut id = base.clone();
+        let mut suffix = b'a';
+        while !used_ids.insert(id.clone()) {
+            id = format!("{}{}", base, suffix as char);
+            suffix += 1;
+        }
+        reference["id"] = json!(id);
+        result.push(reference);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn latex_arguments() {
+        let tex = r"\title[Short]{On \emph{Virtue}}\author{A. Smith \and B. Jones\thanks{Funded.}}";
+        assert_eq!(
+            get_latex_argument(tex, "title").as_deref(),
+            Some(r"On \emph{Virtue}")
+        );
+        assert_eq!(get_latex_argument(tex, "date"), None);
+        assert_eq!(get_latex_argument(r"\title{Unclosed", "title"), None);
+
+        assert_eq!(clean_latex(r"On \emph{Virtue}"), "On Virtue");
+        assert_eq!(
+            clean_latex(r"A. Smith \and B. Jones\thanks{Funded.}"),
+            "A. Smith, B. Jones"
+        );
+        assert_eq!(clean_latex(r"First\\ Second~Part"), "First Second Part");
+    }
+
+    #[test]
+    fn latex_dates() {
+        let get_date = |tex: &str| {
+            get_latex_data(tex)
+                .into_iter()
+                .find(|(key, _)| key == "date")
+                .and_then(|(_, value)| value.into_string())
+        };
+        assert_eq!(get_date(r"\date{2024}").as_deref(), Some("2024"));
+        assert_eq!(get_date(r"\date{2024-05}").as_deref(), Some("2024-05"));
+        assert_eq!(
+            get_date(r"\date{2024-05-17}").as_deref(),
+            Some("2024-05-17")
+        );
+        assert_eq!(get_date(r"\date{\today}"), None);
+    }
+
+    #[test]
+    fn footnote_references() {
+        let markdown = "Text.[^1] More.[^2]\n\n\
+            [^1]: Jane Smith, *The Good Life* (Oxford: Clarendon Press, 1999), 12; \
+            John Doe, \"On Duty,\" *Ethics* 101, no. 3 (1991): 45.\n\
+            [^2]: Smith, *Good Life*, 14.\n";
+        let references = get_footnote_references(markdown).unwrap();
+        assert_eq!(references.len(), 2);
+
+        assert_eq!(references[0]["type"], "book");
+        assert_eq!(references[0]["title"], "The Good Life");
+        assert_eq!(references[0]["publisher"], "Clarendon Press");
+        assert_eq!(references[0]["publisher-place"], "Oxford");
+        assert_eq!(references[0]["author"][0]["family"], "Smith");
+        assert_eq!(references[0]["issued"]["date-parts"][0][0], 1999);
+
+        assert_eq!(references[1]["type"], "article-journal");
+        assert_eq!(references[1]["title"], "On Duty");
+        assert_eq!(references[1]["container-title"], "Ethics");
+        assert_eq!(references[1]["volume"], "101");
+        assert_eq!(references[1]["issue"], "3");
+        assert_eq!(references[1]["issued"]["date-parts"][0][0], 1991);
+    }
+
+    #[test]
+    fn continued_footnotes() {
+        let markdown = "[^1]: Jane Smith, *The Good Life*\n    (Oxford: Clarendon Press, 1999).\n";
+        let references = get_footnote_references(markdown).unwrap();
+        assert_eq!(references.len(), 1);
+        assert_eq!(references[0]["publisher"], "Clarendon Press");
+    }
+
+    #[test]
+    fn images() {
+        let source_dir = tempfile::tempdir().unwrap();
+        let content_dir = tempfile::tempdir().unwrap();
+        fs::create_dir(source_dir.path().join("figs")).unwrap();
+        fs::write(source_dir.path().join("figs/plot.png"), "").unwrap();
+        fs::write(source_dir.path().join("plot.png"), "").unwrap();
+
+        let markdown = "![A](figs/plot) ![B](<plot.png>) ![C](figs/plot) ![D](missing.png)";
+        let (result, count) =
+            collect_images(markdown, source_dir.path(), content_dir.path()).unwrap();
+        assert_eq!(count, 2);
+        assert_eq!(
+            result,
+            "![A](images/plot.png) ![B](images/2-plot.png) ![C](images/plot.png) ![D](missing.png)"
+        );
+        assert!(content_dir.path().join("images/plot.png").is_file());
+        assert!(content_dir.path().join("images/2-plot.png").is_file());
+    }
+}
@@ -1,14 +1,17 @@
-use std::path::Path;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 
 use crate::build;
 use crate::config::CONFIG;
 use crate::formats::Builder;
+use crate::latex_log;
 use crate::metadata::PaperMeta;
 use crate::pandoc_wrap;
 use crate::subprocess;
 use crate::subprocess::RunCommandError;
+use crate::tectonic_wrap;
 use crate::util;
 
 #[derive(Default)]
@@ -27,7 +30,7 @@ impl Builder for LatexBuilder {
         ];
         args.extend_from_slice(&cmds);
 
-        if meta.get_bool(&["latex", "fragment"]).unwrap_or(false) {
+        if meta.latex.fragment {
             if CONFIG.get().verbose {
                 println!("Generating LaTeX fragment...")
             }
@@ -48,38 +51,38 @@ impl Builder for LatexBuilder {
             format!("library_version={}", util::LIB_VERSION),
         ]);
 
-        if let Some(data) = meta.get_data_pairs(&["data"]) {
-            for (k, mut v) in data {
-                if k == "date" {
-                    v = util::get_date_string(meta)?;
-                }
-                // process any markdown inside the variables (italics in a title, for instance)
-                let marked_up = subprocess::run_command(
-                    &pandoc_wrap::get_pandoc_exe_path()?.to_string_lossy(),
-                    &[
-                        "--from".to_string(),
-                        CONFIG.get().pandoc_input_format.clone(),
-                        "--to".to_string(),
-                        "latex".to_string(),
-                    ],
-                    Some(&v),
-                    false,
-                )?;
-
-                args.extend_from_slice(&[
-                    "--variable".to_string(),
-                    format!("{}={{{}}}", k, marked_up.trim()),
-                ]);
+        let mut data = meta.data.get_variables();
+        let title_page_vars = build::get_title_page_variables(meta)?;
+        data.retain(|(k, _)| !title_page_vars.iter().any(|(tk, _)| tk == k));
+        data.extend(title_page_vars);
+        for (k, mut v) in data {
+            if k == "date" {
+                v = util::get_date_string(meta)?;
             }
-        } else {
-            bail!("Missing data map.");
+            // process any markdown inside the variables (italics in a title, for instance)
+            let marked_up = subprocess::run_command(
+                &pandoc_wrap::get_pandoc_exe_path()?.to_string_lossy(),
+                &[
+                    "--from".to_string(),
+                    CONFIG.get().pandoc_input_format.clone(),
+                    "--to".to_string(),
+                    "latex".to_string(),
+                ],
+                Some(&v),
+                false,
+            )?;
+
+            args.extend_from_slice(&[
+                "--variable".to_string(),
+                format!("{}={{{}}}", k, marked_up.trim()),
+            ]);
         }
 
-        if meta.get_bool(&["latex", "ragged"]).unwrap_or(false) {
+        if meta.latex.ragged {
             args.extend_from_slice(&["--variable".to_string(), "ragged=true".to_string()]);
         }
 
-        if let Some(base_font_override) = meta.get_string(&["base_font_override"]) {
+        if let Some(base_font_override) = &meta.base_font_override {
             if CONFIG.get().verbose {
                 println!("Changing base font to {}...", base_font_override);
             }
@@ -88,7 +91,7 @@ impl Builder for LatexBuilder {
                 format!("base_font_override={}", base_font_override),
             ]);
         }
-        if let Some(mono_font_override) = meta.get_string(&["mono_font_override"]) {
+        if let Some(mono_font_override) = &meta.mono_font_override {
             if CONFIG.get().verbose {
                 println!("Changing mono font to {}...", mono_font_override);
             }
@@ -107,7 +110,7 @@ impl Builder for LatexBuilder {
 
     fn finish_file(&self, _output_file_path: &Path, _meta: &PaperMeta) -> Result<Vec<String>> {
         if CONFIG.get().verbose {
-            println!("Packinging LaTeX...");
+            println!("Packaging LaTeX...");
         }
 
         // no-op
@@ -115,6 +118,51 @@ impl Builder for LatexBuilder {
     }
 }
 
+// the PDF gets compiled here, out of the way, so only what was asked for
+//   ends up in the output directory
+const BUILD_DIRECTORY: &str = "build";
+pub const LATEX_ENGINES: &[&str] = &["tectonic", "latexmk", "xelatex", "lualatex"];
+
+fn get_build_dir() -> Result<PathBuf> {
+    let build_dir = std::env::current_dir()
+        .context("Could not get current directory")?
+        .join(".paper_data")
+        .join(BUILD_DIRECTORY);
+    if !build_dir.exists() {
+        fs::create_dir_all(&build_dir)
+            .with_context(|| format!("Could not create directory path {:?}", &build_dir))?;
+        // `.paper_data` is committed, but none of this should be
+        fs::write(build_dir.join(".gitignore"), "*\n")
+            .context("Could not write build directory .gitignore")?;
+    }
+    Ok(build_dir)
+}
+
+fn get_engine_args(engine: &str, tex_path: &Path) -> Result<Vec<String>> {
+    let build_dir = tex_path.parent().unwrap_or(Path::new("."));
+    let mut args: Vec<String> = match engine {
+        "tectonic" => {
+            let mut tectonic_args: Vec<String> =
+                vec!["--color".into(), "never".into(), "--keep-logs".into()];
+            tectonic_args.extend(tectonic_wrap::get_bundle_args()?);
+            tectonic_args
+        }
+        "latexmk" => vec![
+            "-xelatex".into(),
+            "-interaction=nonstopmode".into(),
+            "-halt-on-error".into(),
+            format!("-outdir={}", build_dir.to_string_lossy()),
+        ],
+        _ => vec![
+            "-interaction=nonstopmode".into(),
+            "-halt-on-error".into(),
+            format!("-output-directory={}", build_dir.to_string_lossy()),
+        ],
+    };
+    args.push(tex_path.to_string_lossy().to_string());
+    Ok(args)
+}
+
 #[derive(Default)]
 pub struct LatexPdfBuilder {
     delegate: LatexBuilder,
@@ -122,7 +170,16 @@ pub struct LatexPdfBuilder {
 
 impl Builder for LatexPdfBuilder {
     fn get_output_file_suffix(&self) -> String {
-        self.delegate.get_output_file_suffix()
+        "pdf".to_string()
+    }
+
+    fn get_pandoc_output_path(&self, output_file_path: &Path) -> Result<PathBuf> {
+        let tex_name = output_file_path
+            .with_extension(self.delegate.get_output_file_suffix())
+            .file_name()
+            .context("Output path has no file name")?
+            .to_owned();
+        Ok(get_build_dir()?.join(tex_name))
     }
 
     fn prepare(&mut self, args: &mut Vec<String>, meta: &PaperMeta) -> Result<()> {
@@ -133,24 +190,80 @@ impl Builder for LatexPdfBuilder {
         self.delegate.get_file_list()
     }
 
-    fn finish_file(&self, output_file_path: &Path, _meta: &PaperMeta) -> Result<Vec<String>> {
-        let args = &["--color", "never", &output_file_path.to_string_lossy()];
+    fn finish_file(&self, output_file_path: &Path, meta: &PaperMeta) -> Result<Vec<String>> {
+        let tex_path = self.get_pandoc_output_path(output_file_path)?;
+        let pdf_path = tex_path.with_extension("pdf");
+        let log_path = tex_path.with_extension("log");
+        // don't want to pick up a previous build's results if this one dies
+        for stale in [&pdf_path, &log_path] {
+            if stale.exists() {
+                fs::remove_file(stale).with_context(|| format!("Could not remove {:?}", stale))?;
+            }
+        }
+
+        // the meta file check makes sure it's one of LATEX_ENGINES
+        let engine = meta.latex.engine.clone().unwrap_or("tectonic".to_string());
+        let engine_path = if engine == "tectonic" {
+            tectonic_wrap::get_tectonic_exe_path()?
+        } else {
+            match util::find_executable(&engine) {
+                Some(p) => p,
+                None => bail!(
+                    "LaTeX engine `{}` isn't installed (or isn't on the PATH).",
+                    engine
+                ),
+            }
+        };
+
+        let args = get_engine_args(&engine, &tex_path)?;
         if CONFIG.get().verbose {
             println!("Running LaTeX build command:");
-            println!("\t{}", args.join(" "));
+            println!("\t{} {}", engine_path.to_string_lossy(), args.join(" "));
+        }
+
+        let output = subprocess::run_command(&engine_path.to_string_lossy(), &args, None, true);
+
+        // the log is where the useful information is, pointing at lines in
+        //   the .tex file; it gets mapped back to the content
+        let keep_tex = meta.latex.keep_tex;
+        let reported_tex_path = if keep_tex {
+            output_file_path.with_extension(self.delegate.get_output_file_suffix())
+        } else {
+            tex_path.clone()
+        };
+        let mut error_count = 0;
+        if let Ok(log) = fs::read_to_string(&log_path) {
+            let diagnostics = latex_log::parse_log(&log)?;
+            let content = latex_log::load_content_files(&self.get_file_list())?;
+            error_count = latex_log::report(&diagnostics, &reported_tex_path, &content);
+        }
+        if keep_tex {
+            fs::copy(&tex_path, &reported_tex_path)
+                .with_context(|| format!("Could not copy {:?}", tex_path))?;
         }
 
-        let output = subprocess::run_command("tectonic", args, None, true);
         match output {
-            Ok(stdout) => Ok(stdout.split('\n').map(|s| s.to_string()).collect()),
+            Ok(stdout) => {
+                fs::copy(&pdf_path, output_file_path)
+                    .with_context(|| format!("Could not copy {:?}", pdf_path))?;
+                Ok(stdout.split('\n').map(|s| s.to_string()).collect())
+            }
             Err(e) => match e {
                 RunCommandError::IoErr(ioe) => Err(ioe.into()),
                 RunCommandError::RuntimeErr(out) => {
+                    if error_count > 0 {
+                        bail!("TeX build failed with {} error(s).", error_count);
+                    }
+                    // no log (or nothing we recognized in it), so fall back
+                    //   to whatever the engine said on its way out
                     let stderr = String::from_utf8(out.stderr)?;
                     let tex_err: Vec<&str> = stderr
                         .split('\n')
                         .filter(|s| s.starts_with("error: "))
                         .collect();
+                    if tex_err.is_empty() {
+                        bail!("TeX build failed; see {:?} for details.", log_path);
+                    }
                     bail!("TeX runtime errors: \n{}", tex_err.join("\n"));
                 }
             },
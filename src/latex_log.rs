@@ -0,0 +1,313 @@
+// Making sense of what TeX has to say about a build. Errors and warnings
+//   come out of the log pointing at lines in the generated .tex file, which
+//   nobody ever edits, so each one gets traced back to the content file it
+//   most likely came from.
+//
+// pandoc's markdown reader doesn't carry source positions (only the
+//   commonmark ones do), so the tracing is done by matching up the text
+//   around the offending .tex line with the Markdown. It's approximate, but
+//   it usually lands on the right paragraph.
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use regex::Regex;
+
+use crate::util;
+
+// fraction of a .tex line's words that have to show up in a Markdown line
+//   before we'll call it the source
+const MATCH_THRESHOLD: f64 = 0.5;
+// short words are too common to say much about where a line came from
+const MIN_WORD_LENGTH: usize = 3;
+// how far from the reported line we look for text to match on when the line
+//   itself is just markup
+const CONTEXT_LINES: usize = 2;
+
+#[derive(PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+pub struct TexDiagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // lines in the .tex file, when TeX says
+    pub tex_lines: Option<(usize, usize)>,
+    // what TeX printed of the input where it stopped
+    pub context: Option<String>,
+    // for missing characters, so we can go find them
+    pub character: Option<char>,
+}
+
+pub struct ContentFile {
+    pub path: String,
+    pub lines: Vec<String>,
+}
+
+pub fn load_content_files(paths: &[String]) -> Result<Vec<ContentFile>> {
+    let mut files = vec![];
+    for path in paths {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("Could not read content file {:?}", path))?;
+        files.push(ContentFile {
+            path: path.clone(),
+            lines: text.lines().map(|l| l.to_string()).collect(),
+        });
+    }
+    Ok(files)
+}
+
+pub fn parse_log(log: &str) -> Result<Vec<TexDiagnostic>> {
+    let input_line_search = Regex::new(r"^l\.(\d+) ?(.*)$").context("Could not compile regex")?;
+    // boxes that go bad while \output is active don't come with a line
+    let box_search = Regex::new(
+        r"^((?:Over|Under)full \\[hv]box \([^)]*\))(?:.*?(?:at lines (\d+)--(\d+)|at line (\d+)|detected at line (\d+)))?",
+    )
+    .context("Could not compile regex")?;
+    let missing_search = Regex::new(
+        r"^Missing character: There is no (.+?) (?:\(U\+[0-9A-Fa-f]+\) )?in font (.+?)!",
+    )
+    .context("Could not compile regex")?;
+
+    let lines: Vec<&str> = log.lines().collect();
+    let mut diagnostics = vec![];
+    for (i, line) in lines.iter().enumerate() {
+        if let Some(message) = line.strip_prefix("! ") {
+            // the input position comes a few lines after the message, once
+            //   TeX is done explaining itself
+            let mut tex_lines = None;
+            let mut context = None;
+            for following in lines.iter().skip(i + 1).take(20) {
+                if following.starts_with("! ") {
+                    break;
+                }
+                if let Some(cap) = input_line_search.captures(following) {
+                    let n = cap[1].parse::<usize>().unwrap_or_default();
+                    tex_lines = Some((n, n));
+                    context = Some(cap[2].trim().to_string());
+                    break;
+                }
+            }
+            diagnostics.push(TexDiagnostic {
+                severity: Severity::Error,
+                message: message.trim().to_string(),
+                tex_lines,
+                context,
+                character: None,
+            });
+        } else if let Some(cap) = box_search.captures(line) {
+            let start = cap
+                .get(2)
+                .or(cap.get(4))
+                .or(cap.get(5))
+                .and_then(|m| m.as_str().parse::<usize>().ok());
+            let end = cap
+                .get(3)
+                .and_then(|m| m.as_str().parse::<usize>().ok())
+                .or(start);
+            diagnostics.push(TexDiagnostic {
+                severity: Severity::Warning,
+                message: cap[1].to_string(),
+                tex_lines: start.zip(end),
+                context: None,
+                character: None,
+            });
+        } else if let Some(cap) = missing_search.captures(line) {
+            diagnostics.push(TexDiagnostic {
+                severity: Severity::Warning,
+                message: format!("Missing character: no {} in font {}", &cap[1], &cap[2]),
+                tex_lines: None,
+                context: None,
+                character: cap[1].chars().next(),
+            });
+        }
+    }
+
+    // the same missing character gets reported every time it's used
+    let mut seen = HashSet::new();
+    diagnostics.retain(|d| d.character.is_none() || seen.insert(d.message.clone()));
+
+    Ok(diagnostics)
+}
+
+fn get_significant_words(text: &str) -> HashSet<String> {
+    // drop the LaTeX commands before counting words
+    let stripped: String = text
+        .split('\\')
+        .enumerate()
+        .map(|(i, chunk)| {
+            if i == 0 {
+                chunk
+            } else {
+                chunk.trim_start_matches(|c: char| c.is_ascii_alphabetic())
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(" ");
+    util::get_words(&stripped)
+        .into_iter()
+        .filter(|w| w.chars().count() >= MIN_WORD_LENGTH)
+        .collect()
+}
+
+fn find_text(words: &HashSet<String>, content: &[ContentFile]) -> Option<(String, usize)> {
+    if words.is_empty() {
+        return None;
+    }
+    let mut best: Option<(f64, String, usize)> = None;
+    for file in content {
+        for (i, line) in file.lines.iter().enumerate() {
+            let line_words = util::get_words(line);
+            let score = words.intersection(&line_words).count() as f64 / words.len() as f64;
+            if score >= MATCH_THRESHOLD && best.as_ref().is_none_or(|(s, _, _)| score > *s) {
+                best = Some((score, file.path.clone(), i + 1));
+            }
+        }
+    }
+    best.map(|(_, path, line)| (path, line))
+}
+
+// where in the content this diagnostic most likely came from
+pub fn locate(
+    diag: &TexDiagnostic,
+    tex_lines: &[String],
+    content: &[ContentFile],
+) -> Option<(String, usize)> {
+    if let Some(c) = diag.character {
+        for file in content {
+            for (i, line) in file.lines.iter().enumerate() {
+                if line.contains(c) {
+                    return Some((file.path.clone(), i + 1));
+                }
+            }
+        }
+        return None;
+    }
+
+    if let Some(context) = &diag.context {
+        if let Some(found) = find_text(&get_significant_words(context), content) {
+            return Some(found);
+        }
+    }
+
+    let (start, end) = diag.tex_lines?;
+    let mut tex_text = tex_lines
+        .iter()
+        .skip(start.saturating_sub(1))
+        .take(end.saturating_sub(start) + 1)
+        .cloned()
+        .collect::<Vec<String>>()
+        .join(" ");
+    if get_significant_words(&tex_text).is_empty() {
+        tex_text = tex_lines
+            .iter()
+            .skip(start.saturating_sub(1 + CONTEXT_LINES))
+            .take(end.saturating_sub(start) + 1 + CONTEXT_LINES * 2)
+            .cloned()
+            .collect::<Vec<String>>()
+            .join(" ");
+    }
+    find_text(&get_significant_words(&tex_text), content)
+}
+
+// prints everything in `file:line: severity: message` form, pointing at the
+//   content file where we could figure it out (with a note saying those are
+//   approximate) and the .tex file otherwise; returns how many errors there
+//   were
+pub fn report(diagnostics: &[TexDiagnostic], tex_path: &Path, content: &[ContentFile]) -> usize {
+    let tex_lines: Vec<String> = fs::read_to_string(tex_path)
+        .map(|t| t.lines().map(|l| l.to_string()).collect())
+        .unwrap_or_default();
+
+    let mut error_count = 0;
+    let mut located = false;
+    for diag in diagnostics {
+        let severity = match diag.severity {
+            Severity::Error => {
+                error_count += 1;
+                "error"
+            }
+            Severity::Warning => "warning",
+        };
+        let position = match locate(diag, &tex_lines, content) {
+            Some((path, line)) => {
+                located = true;
+                format!("{}:{}", path, line)
+            }
+            None => match diag.tex_lines {
+                Some((start, _)) => format!("{}:{}", tex_path.to_string_lossy(), start),
+                None => tex_path.to_string_lossy().to_string(),
+            },
+        };
+        eprintln!("{}: {}: {}", position, severity, diag.message);
+    }
+    if located {
+        eprintln!(
+            "(Lines in content files are best guesses from matching up text with the .tex file; they may be a little off.)"
+        );
+    }
+    error_count
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LOG: &str = r#"This is XeTeX, Version 3.141592653-2.6-0.999995 (Tectonic) (preloaded format=xelatex 2024.8.1)
+entering extended mode
+(./paper.tex
+LaTeX2e <2022-11-01> patch level 1
+Missing character: There is no ☃ (U+2603) in font Times New Roman/OT:script=latn;language=dflt;!
+Missing character: There is no ☃ (U+2603) in font Times New Roman/OT:script=latn;language=dflt;!
+
+Overfull \hbox (12.34pt too wide) in paragraph at lines 41--43
+[]\TU/TimesNewRoman(0)/m/n/12 averyveryverylongwordthatwontbreak
+! Undefined control sequence.
+l.57 the \badmacro
+                   {argument} goes here
+Underfull \vbox (badness 10000) has occurred while \output is active
+Overfull \hbox (3.0pt too wide) detected at line 88
+[1] [2] )
+"#;
+
+    #[test]
+    fn parses_a_tectonic_log() {
+        let diagnostics = parse_log(LOG).unwrap();
+        assert_eq!(diagnostics.len(), 5);
+
+        let missing = &diagnostics[0];
+        assert!(missing.severity == Severity::Warning);
+        assert_eq!(missing.character, Some('☃'));
+        assert_eq!(missing.tex_lines, None);
+
+        let overfull = &diagnostics[1];
+        assert!(overfull.severity == Severity::Warning);
+        assert_eq!(overfull.message, "Overfull \\hbox (12.34pt too wide)");
+        assert_eq!(overfull.tex_lines, Some((41, 43)));
+
+        let error = &diagnostics[2];
+        assert!(error.severity == Severity::Error);
+        assert_eq!(error.message, "Undefined control sequence.");
+        assert_eq!(error.tex_lines, Some((57, 57)));
+        assert_eq!(error.context.as_deref(), Some("the \\badmacro"));
+
+        let output_box = &diagnostics[3];
+        assert_eq!(output_box.message, "Underfull \\vbox (badness 10000)");
+        assert_eq!(output_box.tex_lines, None);
+
+        assert_eq!(diagnostics[4].tex_lines, Some((88, 88)));
+    }
+
+    #[test]
+    fn clean_log() {
+        assert!(
+            parse_log("This is XeTeX\nOutput written on paper.pdf (3 pages).\n")
+                .unwrap()
+                .is_empty()
+        );
+    }
+}
@@ -6,16 +6,27 @@ use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 mod build;
 mod cli;
+mod comments;
 mod config;
+mod doctor;
 mod docx;
+mod docx_passes;
 mod fmt;
 mod formats;
+mod html;
+mod import;
 mod latex;
+mod latex_log;
+mod meta_schema;
 pub mod metadata;
 mod pandoc_wrap;
+mod preview;
 mod project_setup;
+mod refs;
 mod save;
 mod subprocess;
+mod tectonic_wrap;
+mod user_config;
 mod util;
 mod watcher;
 mod wc;
@@ -55,10 +66,11 @@ fn _main() -> Result<()> {
                 sub_matches
                     .get_one::<String>("PROJECT_NAME")
                     .expect("required"),
+                sub_matches.get_one::<String>("from").map(Path::new),
             )?;
         }
-        Some(("init", _)) => {
-            project_setup::init_project()?;
+        Some(("init", sub_matches)) => {
+            project_setup::init_project(sub_matches.get_one::<String>("from").map(Path::new))?;
         }
         Some(("dev", _)) => {
             project_setup::dev()?;
@@ -69,18 +81,32 @@ fn _main() -> Result<()> {
                     .get_one::<String>("output-format")
                     .expect("required"),
             )?;
-            let of_specified = match sub_matches.value_source("output-format").expect("required") {
-                clap::parser::ValueSource::DefaultValue => false,
-                _ => true,
-            };
+            let of_specified = !matches!(
+                sub_matches.value_source("output-format").expect("required"),
+                clap::parser::ValueSource::DefaultValue
+            );
 
-            build::build(
-                &output_format,
-                of_specified,
-                *sub_matches
-                    .get_one::<i64>("docx-revision")
-                    .expect("required"),
-            )?;
+            let docx_revision = *sub_matches
+                .get_one::<i64>("docx-revision")
+                .expect("required");
+
+            let strip_annotations = sub_matches.get_flag("strip-annotations");
+
+            if sub_matches.get_flag("verify-reproducible") {
+                build::verify_reproducible(
+                    &output_format,
+                    of_specified,
+                    docx_revision,
+                    strip_annotations,
+                )?;
+            } else {
+                build::build(
+                    &output_format,
+                    of_specified,
+                    docx_revision,
+                    strip_annotations,
+                )?;
+            }
         }
         Some(("save", sub_matches)) => {
             let msg = sub_matches.get_one::<String>("message");
@@ -101,10 +127,10 @@ fn _main() -> Result<()> {
                     .get_one::<String>("output-format")
                     .expect("required"),
             )?;
-            let of_specified = match sub_matches.value_source("output-format").expect("required") {
-                clap::parser::ValueSource::DefaultValue => false,
-                _ => true,
-            };
+            let of_specified = !matches!(
+                sub_matches.value_source("output-format").expect("required"),
+                clap::parser::ValueSource::DefaultValue
+            );
 
             watcher::watch(
                 sub_matches.get_flag("full"),
@@ -114,8 +140,43 @@ fn _main() -> Result<()> {
                 *sub_matches
                     .get_one::<i64>("docx-revision")
                     .expect("required"),
+                *sub_matches.get_one::<u64>("debounce").expect("required"),
+                if sub_matches.get_flag("serve") {
+                    Some(*sub_matches.get_one::<u16>("port").expect("required"))
+                } else {
+                    None
+                },
             )?;
         }
+        Some(("meta", sub_matches)) => match sub_matches.subcommand() {
+            Some(("check", _)) => {
+                meta_schema::check()?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("refs", sub_matches)) => match sub_matches.subcommand() {
+            Some(("sync", _)) => {
+                refs::sync()?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("resources", sub_matches)) => match sub_matches.subcommand() {
+            Some(("update", update_matches)) => {
+                project_setup::update_resources(update_matches.get_flag("force"))?;
+            }
+            _ => unreachable!(),
+        },
+        Some(("import-comments", sub_matches)) => {
+            comments::import_comments(
+                sub_matches
+                    .get_one::<String>("DOCX_FILE")
+                    .expect("required"),
+                sub_matches.get_flag("inline"),
+            )?;
+        }
+        Some(("doctor", _)) => {
+            doctor::doctor()?;
+        }
         Some(("fmt", sub_matches)) => {
             fmt::fmt(
                 !sub_matches.get_flag("no-wrap"),
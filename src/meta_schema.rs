@@ -0,0 +1,499 @@
+// Checking the meta file against what paper expects to find there. A
+//   mistake like `use_ibid: "yes"` or a misspelled `target_word_count`
+//   otherwise just quietly turns into a default, so the file gets checked
+//   whenever it's loaded. The structs in metadata.rs are the schema: unknown
+//   keys are warnings (pandoc and the filters see the whole file, after all)
+//   but values of the wrong type or with invalid contents are errors. Serde
+//   stops at the first bad value, so that's the only error that gets shown.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::{bail, Context, Result};
+use serde_json::Value;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+use yaml_rust::Yaml;
+
+use crate::metadata::{self, PaperMeta};
+use crate::util;
+
+// pandoc's own metadata and template variables (from the "Metadata
+//   variables" and "Variables for LaTeX" parts of its manual), which go
+//   straight through to pandoc
+const PANDOC_KEYS: &[&str] = &[
+    // metadata
+    "subtitle",
+    "abstract",
+    "abstract-title",
+    "keywords",
+    "subject",
+    "description",
+    "category",
+    "thanks",
+    "institute",
+    "lang",
+    "dir",
+    "header-includes",
+    "include-before",
+    "include-after",
+    "toc",
+    "toc-depth",
+    "toc-title",
+    "lof",
+    "lot",
+    "numbersections",
+    "secnumdepth",
+    // citations
+    "csl",
+    "bibliography",
+    "references",
+    "citation-abbreviations",
+    "nocite",
+    "link-citations",
+    "link-bibliography",
+    "reference-section-title",
+    "suppress-bibliography",
+    "notes-after-punctuation",
+    "lang-citations",
+    // layout
+    "documentclass",
+    "classoption",
+    "geometry",
+    "papersize",
+    "margin-left",
+    "margin-right",
+    "margin-top",
+    "margin-bottom",
+    "hyperrefoptions",
+    "indent",
+    "linestretch",
+    "pagestyle",
+    "block-headings",
+    // fonts
+    "fontsize",
+    "fontfamily",
+    "fontfamilyoptions",
+    "mainfont",
+    "mainfontoptions",
+    "sansfont",
+    "sansfontoptions",
+    "monofont",
+    "monofontoptions",
+    "mathfont",
+    "mathfontoptions",
+    "CJKmainfont",
+    "CJKoptions",
+    "fontenc",
+    "microtypeoptions",
+    // links
+    "colorlinks",
+    "linkcolor",
+    "filecolor",
+    "citecolor",
+    "urlcolor",
+    "toccolor",
+    "links-as-notes",
+    "urlstyle",
+    // languages
+    "mainlang",
+    "otherlangs",
+    "babel-lang",
+    "babel-otherlangs",
+    "babel-newcommands",
+    "polyglossia-lang",
+    "polyglossia-otherlangs",
+    // PDF properties
+    "pdfauthor",
+    "pdftitle",
+    "pdfsubject",
+    "pdfkeywords",
+    "pdfcreator",
+    "pdfproducer",
+];
+
+pub struct MetaProblem {
+    pub is_error: bool,
+    pub path: Vec<String>,
+    pub message: String,
+}
+
+// where each key (and list item) sits in the file, by its path
+#[derive(Default)]
+struct PositionRecorder {
+    positions: HashMap<Vec<String>, Marker>,
+    path: Vec<String>,
+    // one entry per open mapping or sequence: for mappings, whether the
+    //   next scalar is a key; for sequences, the index of the next item
+    frames: Vec<(bool, usize)>,
+    in_sequence: Vec<bool>,
+}
+
+impl PositionRecorder {
+    // called once a value (scalar or whole container) is finished
+    fn finish_value(&mut self) {
+        if let (Some(frame), Some(is_seq)) = (self.frames.last_mut(), self.in_sequence.last()) {
+            if *is_seq {
+                frame.1 += 1;
+            } else {
+                frame.0 = true;
+            }
+            self.path.pop();
+        }
+    }
+
+    // called when a value (scalar or container) starts
+    fn start_value(&mut self, mark: Marker) {
+        if let (Some(frame), Some(true)) = (self.frames.last(), self.in_sequence.last()) {
+            self.path.push(frame.1.to_string());
+            self.positions.entry(self.path.clone()).or_insert(mark);
+        }
+    }
+}
+
+impl MarkedEventReceiver for PositionRecorder {
+    fn on_event(&mut self, ev: Event, mark: Marker) {
+        match ev {
+            Event::Scalar(value, _, _, _) => {
+                let is_key = matches!(
+                    (self.frames.last(), self.in_sequence.last()),
+                    (Some((true, _)), Some(false))
+                );
+                if is_key {
+                    if let Some(frame) = self.frames.last_mut() {
+                        frame.0 = false;
+                    }
+                    self.path.push(value);
+                    self.positions.entry(self.path.clone()).or_insert(mark);
+                } else {
+                    self.start_value(mark);
+                    self.finish_value();
+                }
+            }
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                self.start_value(mark);
+                self.frames.push((true, 0));
+                self.in_sequence.push(matches!(ev, Event::SequenceStart(_)));
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.frames.pop();
+                self.in_sequence.pop();
+                self.finish_value();
+            }
+            Event::Alias(_) => {
+                self.start_value(mark);
+                self.finish_value();
+            }
+            _ => {}
+        }
+    }
+}
+
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut curr = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let cost = if ca == *cb { 0 } else { 1 };
+            curr.push((prev[j] + cost).min(prev[j + 1] + 1).min(curr[j] + 1));
+        }
+        prev = curr;
+    }
+    prev[b.len()]
+}
+
+// serde's message for a key a struct doesn't have is "unknown field `x`,
+//   expected one of `a`, `b`"; pulls out the key and the ones it could
+//   have been
+fn get_unknown_field(message: &str) -> Option<(String, Vec<String>)> {
+    let rest = message.strip_prefix("unknown field ")?;
+    let mut quoted = rest.split('`').skip(1).step_by(2).map(String::from);
+    let key = quoted.next()?;
+    Some((key, quoted.collect()))
+}
+
+fn remove_key(value: &mut Value, path: &[String], key: &str) -> bool {
+    let mut curr = value;
+    for p in path {
+        curr = match curr {
+            Value::Object(map) => match map.get_mut(p) {
+                Some(v) => v,
+                None => return false,
+            },
+            Value::Array(items) => match p.parse::<usize>().ok().and_then(|i| items.get_mut(i)) {
+                Some(v) => v,
+                None => return false,
+            },
+            _ => return false,
+        };
+    }
+    match curr {
+        Value::Object(map) => map.remove(key).is_some(),
+        _ => false,
+    }
+}
+
+// the settings, if the file is good enough to read them from, and whatever
+//   is wrong with it
+pub fn check_meta(root: &Yaml) -> (Option<PaperMeta>, Vec<MetaProblem>) {
+    let mut value = metadata::yaml_to_json(root);
+    let mut problems = vec![];
+    // an empty file is just all defaults
+    if value.is_null() {
+        return (Some(PaperMeta::default()), problems);
+    }
+
+    // every unknown key stops things, so take it out and go around again
+    loop {
+        let err = match serde_path_to_error::deserialize::<_, PaperMeta>(&value) {
+            Ok(meta) => return (Some(meta), problems),
+            Err(err) => err,
+        };
+        let mut path: Vec<String> = err
+            .path()
+            .iter()
+            .map(|seg| match seg {
+                serde_path_to_error::Segment::Seq { index } => index.to_string(),
+                serde_path_to_error::Segment::Map { key } => key.clone(),
+                serde_path_to_error::Segment::Enum { variant } => variant.clone(),
+                serde_path_to_error::Segment::Unknown => "?".to_string(),
+            })
+            .collect();
+        let message = err.inner().to_string();
+
+        if let Some((key, expected)) = get_unknown_field(&message) {
+            if path.last() == Some(&key) {
+                path.pop();
+            }
+            if remove_key(&mut value, &path, &key) {
+                path.push(key.clone());
+                if path.len() > 1 || !PANDOC_KEYS.contains(&key.as_str()) {
+                    // a misspelled pandoc key is as likely as one of ours
+                    let pandoc_keys = if path.len() == 1 { PANDOC_KEYS } else { &[] };
+                    let suggestion = expected
+                        .iter()
+                        .map(|e| e.as_str())
+                        .chain(pandoc_keys.iter().copied())
+                        .map(|e| (edit_distance(&key, e), e))
+                        .filter(|(d, _)| *d <= 2 || *d <= key.len() / 4)
+                        .min()
+                        .map(|(_, s)| format!(" (did you mean `{}`?)", s))
+                        .unwrap_or_default();
+                    problems.push(MetaProblem {
+                        is_error: false,
+                        message: format!("unknown key `{}`{}", path.join("."), suggestion),
+                        path,
+                    });
+                }
+                continue;
+            }
+        }
+
+        let message = if path.is_empty() {
+            message
+        } else {
+            format!("`{}`: {}", path.join("."), message)
+        };
+        problems.push(MetaProblem {
+            is_error: true,
+            path,
+            message,
+        });
+        return (None, problems);
+    }
+}
+
+// `file:line:col: severity: message`, for each problem
+pub fn format_problems(meta_path: &Path, text: &str, problems: &[MetaProblem]) -> Vec<String> {
+    let mut recorder = PositionRecorder::default();
+    let _ = Parser::new(text.chars()).load(&mut recorder, true);
+
+    let display_path = meta_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .unwrap_or(meta_path.to_string_lossy().to_string());
+    // in the order they show up in the file, rather than the order serde
+    //   came across them
+    let mut lines: Vec<_> = problems
+        .iter()
+        .map(|p| {
+            let marker = recorder.positions.get(&p.path);
+            let position = match marker {
+                Some(m) => format!("{}:{}:{}", display_path, m.line(), m.col() + 1),
+                None => display_path.clone(),
+            };
+            let severity = if p.is_error { "error" } else { "warning" };
+            (
+                marker.map(|m| m.index()).unwrap_or(usize::MAX),
+                format!("{}: {}: {}", position, severity, p.message),
+            )
+        })
+        .collect();
+    lines.sort_by_key(|(index, _)| *index);
+    lines.into_iter().map(|(_, line)| line).collect()
+}
+
+// the meta file gets loaded over and over during a single command, so only
+//   complain about a given version of it once
+static LAST_VALIDATED: Mutex<Option<String>> = Mutex::new(None);
+
+// reads the settings, printing any warnings and failing if there's an error
+pub fn validate(meta_path: &Path, root: &Yaml) -> Result<PaperMeta> {
+    let text = fs::read_to_string(meta_path)
+        .with_context(|| format!("Could not read file at {:?}", meta_path))?;
+    let (meta, problems) = check_meta(root);
+    let already_shown = LAST_VALIDATED
+        .lock()
+        .map(|last| last.as_deref() == Some(text.as_str()))
+        .unwrap_or(false);
+    if !already_shown {
+        for line in format_problems(meta_path, &text, &problems) {
+            eprintln!("{}", line);
+        }
+    }
+
+    let Some(meta) = meta else {
+        bail!(
+            "Problem in {:?}; see above (or run `paper meta check`).",
+            meta_path
+        );
+    };
+
+    if let Ok(mut last) = LAST_VALIDATED.lock() {
+        *last = Some(text);
+    }
+    Ok(meta)
+}
+
+pub fn check() -> Result<()> {
+    let meta_path = util::find_meta(None)?;
+    let text = fs::read_to_string(&meta_path)
+        .with_context(|| format!("Could not read file at {:?}", meta_path))?;
+    let root = util::load_yml_file(&meta_path)?;
+
+    let (_, problems) = check_meta(&root);
+    for line in format_problems(&meta_path, &text, &problems) {
+        println!("{}", line);
+    }
+    let error_count = problems.iter().filter(|p| p.is_error).count();
+    let warning_count = problems.len() - error_count;
+    if error_count > 0 {
+        bail!(
+            "{} error(s) and {} warning(s) in {:?}.",
+            error_count,
+            warning_count,
+            meta_path
+        );
+    }
+    if warning_count > 0 {
+        println!("{} warning(s), but no errors.", warning_count);
+    } else {
+        println!("No problems found in {:?}.", meta_path);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use yaml_rust::YamlLoader;
+
+    fn check_text(text: &str) -> (Option<PaperMeta>, Vec<String>) {
+        let root = YamlLoader::load_from_str(text).unwrap().remove(0);
+        let (meta, problems) = check_meta(&root);
+        let lines = format_problems(Path::new("paper_meta.yml"), text, &problems);
+        (meta, lines)
+    }
+
+    #[test]
+    fn unknown_keys_are_warnings_with_suggestions() {
+        let text = "use_ibid: true\ntarget_wordcount: 500\ndocx:\n  paper_size: a4\n  magrins: 1\nlang: en-US\n";
+        let (meta, lines) = check_text(text);
+        assert!(meta.unwrap().use_ibid);
+        assert_eq!(
+            lines,
+            vec![
+                "paper_meta.yml:2:1: warning: unknown key `target_wordcount` (did you mean `target_word_count`?)",
+                "paper_meta.yml:5:3: warning: unknown key `docx.magrins` (did you mean `margins`?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn pandoc_keys_pass_through() {
+        let text = "csl: chicago.csl\nmainfont: Gentium Plus\nfontsize: 12pt\ngeometry: margin=1in\nlink-citations: true\nmianfont: Gentium\n";
+        let (meta, lines) = check_text(text);
+        assert!(meta.is_some());
+        assert_eq!(
+            lines,
+            vec!["paper_meta.yml:6:1: warning: unknown key `mianfont` (did you mean `mainfont`?)"]
+        );
+    }
+
+    #[test]
+    fn nested_unknown_keys_get_their_whole_path() {
+        let text = "data:\n  author:\n    - Jane Doe\n    - nmae: John Roe\n  course: PHIL 101\n";
+        let (meta, lines) = check_text(text);
+        // anything goes in `data`, for the title page templates
+        assert_eq!(meta.unwrap().data.extra.len(), 1);
+        assert_eq!(
+            lines,
+            vec!["paper_meta.yml:4:7: warning: unknown key `data.author.1.nmae` (did you mean `name`?)"]
+        );
+    }
+
+    #[test]
+    fn bad_values_are_errors() {
+        let (meta, lines) = check_text("use_ibid: \"yes\"\n");
+        assert!(meta.is_none());
+        assert_eq!(
+            lines,
+            vec!["paper_meta.yml:1:1: error: `use_ibid`: invalid type: string \"yes\", expected a boolean"]
+        );
+
+        let (meta, lines) = check_text("docx:\n  margins:\n    top: 1\n    left: 3furlongs\n");
+        assert!(meta.is_none());
+        assert_eq!(
+            lines,
+            vec!["paper_meta.yml:4:5: error: `docx.margins.left`: invalid value: string \"3furlongs\", expected a length like `1`, `2.5cm`, or `72pt`"]
+        );
+
+        for text in [
+            "default_format: pdf\n",
+            "docx:\n  paper_size: legal\n",
+            "docx:\n  page_numbers: [header]\n",
+            "latex:\n  engine: pdftex\n",
+            "data:\n  date: next tuesday\n",
+        ] {
+            assert!(
+                check_text(text).0.is_none(),
+                "{:?} should be an error",
+                text
+            );
+        }
+    }
+
+    #[test]
+    fn values_get_normalized() {
+        let text = "data:\n  date: \"[DATE]\"\ndocx:\n  paper_size: A4\n  line_spacing: double\n  margins: 2.54cm\n";
+        let (meta, lines) = check_text(text);
+        assert!(lines.is_empty());
+        let meta = meta.unwrap();
+        assert_eq!(meta.data.date, None);
+        assert_eq!(meta.docx.paper_size.as_deref(), Some("a4"));
+        assert_eq!(meta.docx.line_spacing, Some(2.0));
+        assert!(
+            matches!(meta.docx.margins, Some(metadata::Margins::All(m)) if (m - 1.0).abs() < 1e-9)
+        );
+    }
+
+    #[test]
+    fn null_document_is_all_defaults() {
+        let (meta, lines) = check_text("~\n");
+        assert!(lines.is_empty());
+        assert!(meta.unwrap().sources.is_empty());
+    }
+}
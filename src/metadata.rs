@@ -1,192 +1,664 @@
-use anyhow::{bail, Result};
-use yaml_rust::{yaml, Yaml};
+// The meta file, as the rest of paper sees it. Pandoc gets handed the file
+//   itself, so anything in here is only what paper reads; keys it doesn't
+//   know about are fine (and the ones in `data` get passed along to the
+//   title page templates). These structs are also what the file gets checked
+//   against (see meta_schema.rs), so adding an option means adding it here
+//   and to the README.
 
+use std::collections::BTreeMap;
+use std::fmt;
+
+use anyhow::Result;
+use serde::de::value::MapAccessDeserializer;
+use serde::de::{Error, MapAccess, SeqAccess, Unexpected, Visitor};
+use serde::{Deserialize, Deserializer};
+use serde_json::Value;
+use yaml_rust::Yaml;
+
+use crate::formats;
+use crate::latex;
+use crate::meta_schema;
 use crate::util;
 
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
 pub struct PaperMeta {
-    _root: Yaml,
+    pub data: MetaData,
+    pub use_ibid: bool,
+    pub target_word_count: Option<i64>,
+    pub sentinels: Vec<String>,
+    pub sources: Vec<String>,
+    pub hooks: Hooks,
+    pub refs: Refs,
+    #[serde(deserialize_with = "text")]
+    pub vulgate_cite_key: Option<String>,
+    pub no_title_page: bool,
+    pub title_page: TitlePage,
+    #[serde(deserialize_with = "text")]
+    pub base_font_override: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub mono_font_override: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub filename: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub assignment: Option<String>,
+    #[serde(deserialize_with = "output_format")]
+    pub default_format: Option<String>,
+    // how `data.date` shows up on title pages, as a strftime string
+    #[serde(deserialize_with = "text")]
+    pub date_format: Option<String>,
+    pub docx: DocxMeta,
+    pub latex: LatexMeta,
 }
 
-impl PaperMeta {
-    pub fn new() -> Result<Self> {
-        let meta_path = util::find_meta(None)?;
-        match util::load_yml_file(&meta_path) {
-            Ok(data) => {
-                let mut me = PaperMeta { _root: data };
-                match me.get_string(&["data", "date"]) {
-                    Some(s) => {
-                        if s == "[DATE]" {
-                            me.set_null(&["data", "date"])?
-                        }
-                    }
-                    None => me.set_null(&["data", "date"])?,
-                }
-                Ok(me)
-            }
-            Err(e) => Err(e),
-        }
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default)]
+pub struct MetaData {
+    #[serde(deserialize_with = "date")]
+    pub date: Option<String>,
+    #[serde(rename = "author", deserialize_with = "authors")]
+    pub authors: Vec<Author>,
+    #[serde(deserialize_with = "text")]
+    pub title: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub subtitle: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub class_mnemonic: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub class_name: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub professor: Option<String>,
+    #[serde(deserialize_with = "one_or_many")]
+    pub keywords: Vec<String>,
+    #[serde(deserialize_with = "text")]
+    pub description: Option<String>,
+    // whatever else people want on their title pages
+    #[serde(flatten)]
+    pub extra: BTreeMap<String, Value>,
+}
+
+// either just a name or the whole works; `name` wins over `given` and
+//   `family` for display, but those are handy for sorting out which part is
+//   the last name
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Author {
+    #[serde(deserialize_with = "text")]
+    pub name: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub given: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub family: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub affiliation: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub email: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub orcid: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Hooks {
+    #[serde(deserialize_with = "text")]
+    pub on_build_success: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub on_build_failure: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub on_target_reached: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Refs {
+    #[serde(deserialize_with = "text")]
+    pub sync_from: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub bibliography: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TitlePage {
+    pub show_word_count: bool,
+    #[serde(deserialize_with = "text")]
+    pub pledge: Option<String>,
+}
+
+// either one length for all of them or a map of specific sides, in inches
+#[derive(Clone, Debug)]
+pub enum Margins {
+    All(f64),
+    Sides(MarginSides),
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct MarginSides {
+    #[serde(deserialize_with = "length")]
+    pub top: Option<f64>,
+    #[serde(deserialize_with = "length")]
+    pub right: Option<f64>,
+    #[serde(deserialize_with = "length")]
+    pub bottom: Option<f64>,
+    #[serde(deserialize_with = "length")]
+    pub left: Option<f64>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct StyleMeta {
+    #[serde(deserialize_with = "text")]
+    pub font: Option<String>,
+    pub size: Option<f64>,
+    pub line_spacing: Option<f64>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct XPathRule {
+    #[serde(deserialize_with = "text")]
+    pub part: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub xpath: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub attribute: Option<String>,
+    #[serde(deserialize_with = "text")]
+    pub value: Option<String>,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct DocxMeta {
+    #[serde(deserialize_with = "paper_size")]
+    pub paper_size: Option<String>,
+    pub margins: Option<Margins>,
+    #[serde(deserialize_with = "line_spacing")]
+    pub line_spacing: Option<f64>,
+    #[serde(deserialize_with = "text")]
+    pub header: Option<String>,
+    #[serde(deserialize_with = "page_numbers")]
+    pub page_numbers: Option<String>,
+    pub styles: BTreeMap<String, StyleMeta>,
+    pub passes: BTreeMap<String, bool>,
+    pub xpath_rules: Vec<XPathRule>,
+    // these two also get set from the command line
+    pub revision: Option<i64>,
+    pub strip_annotations: bool,
+}
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct LatexMeta {
+    pub fragment: bool,
+    pub ragged: bool,
+    pub keep_tex: bool,
+    #[serde(deserialize_with = "engine")]
+    pub engine: Option<String>,
+}
+
+// numbers are fine where text is expected (`class_mnemonic: 101`)
+fn text<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Value::Array(_)) => Err(D::Error::invalid_type(Unexpected::Seq, &"text")),
+        Some(Value::Object(_)) => Err(D::Error::invalid_type(Unexpected::Map, &"text")),
+        Some(v) => Ok(value_to_string(&v)),
     }
+}
 
-    fn fetch_node(&self, keychain: &[&str]) -> Option<Yaml> {
-        if keychain.is_empty() {
-            return Some(self._root.clone());
-        }
-        let mut curr = self._root.as_hash().unwrap();
-        for (i, k) in keychain.iter().enumerate() {
-            let key = Yaml::String(k.to_string());
-            match curr.get(&key) {
-                None => return None,
-                Some(val) => {
-                    if i == keychain.len() - 1 {
-                        return Some(val.clone());
-                    }
-                    match val {
-                        Yaml::Hash(vh) => {
-                            curr = vh;
-                        }
-                        _ => return None,
-                    }
-                }
-            }
-        }
-        None
+// a fixed set of words, where `A4` is as good as `a4`
+fn one_of<'de, D: Deserializer<'de>>(
+    deserializer: D,
+    options: &[&str],
+) -> Result<Option<String>, D::Error> {
+    let Some(s) = text(deserializer)? else {
+        return Ok(None);
+    };
+    let lower = s.to_lowercase();
+    if options.contains(&lower.as_str()) {
+        return Ok(Some(lower));
     }
+    let expected = format!(
+        "one of {}",
+        options
+            .iter()
+            .map(|o| format!("`{}`", o))
+            .collect::<Vec<_>>()
+            .join(", ")
+    );
+    Err(D::Error::invalid_value(
+        Unexpected::Str(&s),
+        &expected.as_str(),
+    ))
+}
+
+fn output_format<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    one_of(deserializer, formats::OUTPUT_FORMATS)
+}
+
+fn paper_size<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    one_of(deserializer, &["letter", "a4"])
+}
+
+fn page_numbers<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    one_of(deserializer, &["footer", "header", "none"])
+}
+
+fn engine<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    one_of(deserializer, latex::LATEX_ENGINES)
+}
 
-    pub fn contains(&self, keychain: &[&str]) -> bool {
-        self.fetch_node(keychain).is_some()
+// `YYYY`, `YYYY-MM`, `YYYY-MM-DD`, or a date and time; the "[DATE]"
+//   placeholder from a fresh project counts as no date at all
+fn date<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<String>, D::Error> {
+    match text(deserializer)? {
+        Some(d) if d == "[DATE]" => Ok(None),
+        Some(d) if util::parse_due_date(&d).is_err() => Err(D::Error::invalid_value(
+            Unexpected::Str(&d),
+            &"a date like 2024, 2024-12, 2024-12-31, or 2024-12-31T23:59-05:00",
+        )),
+        other => Ok(other),
     }
+}
 
-    pub fn get_bool(&self, keychain: &[&str]) -> Option<bool> {
-        self.fetch_node(keychain).and_then(|n| n.as_bool())
+// `single`, `double`, or a multiple
+fn line_spacing<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        None => Ok(None),
+        Some(Value::Number(n)) => Ok(n.as_f64()),
+        Some(Value::String(s)) => match s.to_lowercase().as_str() {
+            "single" => Ok(Some(1.0)),
+            "double" => Ok(Some(2.0)),
+            _ => Err(D::Error::invalid_value(
+                Unexpected::Str(&s),
+                &"`single`, `double`, or a number",
+            )),
+        },
+        Some(_) => Err(D::Error::custom("expected `single`, `double`, or a number")),
     }
+}
+
+const LENGTH_EXPECTED: &str = "a length like `1`, `2.5cm`, or `72pt`";
+
+// bare numbers are inches, or there can be a unit tacked on like "2.5cm"
+pub fn parse_length(length: &str) -> Option<f64> {
+    let s = length.trim();
+    let (num, per_inch) = [("in", 1.0), ("cm", 2.54), ("mm", 25.4), ("pt", 72.0)]
+        .iter()
+        .find_map(|(unit, per_inch)| s.strip_suffix(unit).map(|n| (n, *per_inch)))
+        .unwrap_or((s, 1.0));
+    num.trim().parse::<f64>().ok().map(|val| val / per_inch)
+}
+
+struct LengthVisitor;
 
-    pub fn get_int(&self, keychain: &[&str]) -> Option<i64> {
-        self.fetch_node(keychain).and_then(|n| n.as_i64())
+impl<'de> Visitor<'de> for LengthVisitor {
+    type Value = f64;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(LENGTH_EXPECTED)
     }
 
-    pub fn get_float(&self, keychain: &[&str]) -> Option<f64> {
-        self.fetch_node(keychain).and_then(|n| n.as_f64())
+    fn visit_i64<E: Error>(self, v: i64) -> Result<f64, E> {
+        Ok(v as f64)
     }
 
-    pub fn get_string(&self, keychain: &[&str]) -> Option<String> {
-        self.fetch_node(keychain).and_then(|n| n.into_string())
+    fn visit_u64<E: Error>(self, v: u64) -> Result<f64, E> {
+        Ok(v as f64)
     }
 
-    pub fn get_vec_string(&self, keychain: &[&str]) -> Option<Vec<String>> {
-        match self.fetch_node(keychain) {
-            None => None,
-            Some(node) => match node {
-                Yaml::Array(narr) => {
-                    let mut v: Vec<String> = Vec::new();
-                    for el in narr {
-                        match el.into_string() {
-                            Some(s) => v.push(s),
-                            None => return None,
-                        }
-                    }
-                    Some(v)
-                }
-                _ => None,
-            },
-        }
+    fn visit_f64<E: Error>(self, v: f64) -> Result<f64, E> {
+        Ok(v)
     }
 
-    // HACKHACK
-    pub fn get_data_pairs(&self, keychain: &[&str]) -> Option<Vec<(String, String)>> {
-        match self.fetch_node(keychain) {
-            None => None,
-            Some(node) => match node {
-                Yaml::Hash(nh) => {
-                    let mut vec = Vec::new();
-                    for (key, val) in nh {
-                        match key.into_string() {
-                            None => {}
-                            Some(k) => match val.into_string() {
-                                None => {}
-                                Some(v) => {
-                                    let el = (k, v);
-                                    vec.push(el);
-                                }
-                            },
-                        }
-                    }
-                    Some(vec)
-                }
-                _ => None,
-            },
+    fn visit_str<E: Error>(self, v: &str) -> Result<f64, E> {
+        parse_length(v).ok_or_else(|| E::invalid_value(Unexpected::Str(v), &LENGTH_EXPECTED))
+    }
+}
+
+fn length<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<f64>, D::Error> {
+    deserializer.deserialize_any(LengthVisitor).map(Some)
+}
+
+struct MarginsVisitor;
+
+impl<'de> Visitor<'de> for MarginsVisitor {
+    type Value = Margins;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} or a map of sides", LENGTH_EXPECTED)
+    }
+
+    fn visit_i64<E: Error>(self, v: i64) -> Result<Margins, E> {
+        LengthVisitor.visit_i64(v).map(Margins::All)
+    }
+
+    fn visit_u64<E: Error>(self, v: u64) -> Result<Margins, E> {
+        LengthVisitor.visit_u64(v).map(Margins::All)
+    }
+
+    fn visit_f64<E: Error>(self, v: f64) -> Result<Margins, E> {
+        LengthVisitor.visit_f64(v).map(Margins::All)
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Margins, E> {
+        LengthVisitor.visit_str(v).map(Margins::All)
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Margins, A::Error> {
+        MarginSides::deserialize(MapAccessDeserializer::new(map)).map(Margins::Sides)
+    }
+}
+
+impl<'de> Deserialize<'de> for Margins {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(MarginsVisitor)
+    }
+}
+
+// `keywords: ethics` and `keywords: [ethics, virtue]` both work
+fn one_or_many<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<String>, D::Error> {
+    let to_string = |v: &Value| {
+        value_to_string(v).ok_or_else(|| D::Error::custom("expected text or a list of text"))
+    };
+    match Value::deserialize(deserializer)? {
+        Value::Array(items) => items.iter().map(to_string).collect(),
+        other => Ok(vec![to_string(&other)?]),
+    }
+}
+
+// `author: Jane Doe`, a single map, or a list of either
+struct Authors(Vec<Author>);
+
+struct AuthorsVisitor;
+
+impl<'de> Visitor<'de> for AuthorsVisitor {
+    type Value = Authors;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a name, a map of author details, or a list of either")
+    }
+
+    fn visit_str<E: Error>(self, v: &str) -> Result<Authors, E> {
+        Ok(Authors(vec![Author {
+            name: Some(v.to_string()),
+            ..Default::default()
+        }]))
+    }
+
+    fn visit_unit<E: Error>(self) -> Result<Authors, E> {
+        Ok(Authors(vec![]))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(self, map: A) -> Result<Authors, A::Error> {
+        Ok(Authors(vec![Author::deserialize(
+            MapAccessDeserializer::new(map),
+        )?]))
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Authors, A::Error> {
+        let mut authors = vec![];
+        while let Some(Authors(more)) = seq.next_element()? {
+            authors.extend(more);
         }
+        Ok(Authors(authors))
+    }
+}
+
+impl<'de> Deserialize<'de> for Authors {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_any(AuthorsVisitor)
+    }
+}
+
+fn authors<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<Author>, D::Error> {
+    Ok(Authors::deserialize(deserializer)?.0)
+}
+
+fn value_to_string(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+// serde can't read yaml_rust's documents directly, but JSON is close enough;
+//   nulls get dropped so they just leave things at their defaults
+pub fn yaml_to_json(yaml: &Yaml) -> Value {
+    match yaml {
+        Yaml::String(s) => Value::String(s.clone()),
+        Yaml::Integer(i) => Value::from(*i),
+        Yaml::Real(r) => match r.parse::<f64>() {
+            Ok(f) => Value::from(f),
+            Err(_) => Value::String(r.clone()),
+        },
+        Yaml::Boolean(b) => Value::Bool(*b),
+        Yaml::Array(items) => Value::Array(items.iter().map(yaml_to_json).collect()),
+        Yaml::Hash(h) => Value::Object(
+            h.iter()
+                .filter(|(_, v)| !v.is_null())
+                .filter_map(|(k, v)| {
+                    let key = match k {
+                        Yaml::String(s) | Yaml::Real(s) => s.clone(),
+                        Yaml::Integer(i) => i.to_string(),
+                        Yaml::Boolean(b) => b.to_string(),
+                        _ => return None,
+                    };
+                    Some((key, yaml_to_json(v)))
+                })
+                .collect(),
+        ),
+        _ => Value::Null,
     }
+}
 
-    // dives through nested HashMaps following a chain of string keys
-    //   returns the end of the chain so something can be inserted to it,
-    //   creating new nested maps along the way as needed.
-    fn get_end_of_chain<'a>(
-        mut data: &'a mut Yaml,
-        keychain: impl Iterator<Item = String>,
-    ) -> Result<&'a mut Yaml> {
-        for key in keychain {
-            match data {
-                Yaml::Hash(vh) => {
-                    data = vh
-                        .entry(Yaml::String(key))
-                        .or_insert_with(|| Yaml::Hash(yaml::Hash::new()))
-                }
-                _ => bail!("Non-hash key {} in keychain.", key),
+impl PaperMeta {
+    pub fn new() -> Result<Self> {
+        let meta_path = util::find_meta(None)?;
+        let data = util::load_yml_file(&meta_path)?;
+        meta_schema::validate(&meta_path, &data)
+    }
+}
+
+impl MetaData {
+    // everything in the block that can go into a template variable, with
+    //   anything that isn't a simple value left out
+    pub fn get_variables(&self) -> Vec<(String, String)> {
+        let mut vars = vec![];
+        let author = self.get_author_string();
+        let known = [
+            ("date", &self.date),
+            ("author", &author),
+            ("title", &self.title),
+            ("subtitle", &self.subtitle),
+            ("class_mnemonic", &self.class_mnemonic),
+            ("class_name", &self.class_name),
+            ("professor", &self.professor),
+            ("description", &self.description),
+        ];
+        for (k, v) in known {
+            if let Some(v) = v {
+                vars.push((k.to_string(), v.clone()));
             }
         }
-        Ok(data)
+        if !self.keywords.is_empty() {
+            vars.push(("keywords".to_string(), self.keywords.join(", ")));
+        }
+        // giving the same variable more than once makes it a list in the
+        //   templates
+        for a in &self.authors {
+            vars.push(("authors".to_string(), a.get_title_page_block()));
+        }
+        for (k, v) in &self.extra {
+            if let Some(v) = value_to_string(v) {
+                vars.push((k.clone(), v));
+            }
+        }
+        vars
+    }
+
+    pub fn get_author_names(&self) -> Vec<String> {
+        self.authors
+            .iter()
+            .map(|a| a.get_name())
+            .filter(|n| !n.is_empty())
+            .collect()
     }
 
-    fn set_node(&mut self, keychain: &[&str], value: Yaml) -> Result<()> {
-        if keychain.is_empty() {
-            bail!("Cannot set value without at least one key.")
+    // "A", "A and B", or "A, B, and C"
+    pub fn get_author_string(&self) -> Option<String> {
+        let names = self.get_author_names();
+        match names.len() {
+            0 => None,
+            1 => Some(names[0].clone()),
+            2 => Some(format!("{} and {}", names[0], names[1])),
+            n => Some(format!(
+                "{}, and {}",
+                names[..n - 1].join(", "),
+                names[n - 1]
+            )),
         }
+    }
+}
 
-        let last = keychain[keychain.len() - 1];
-        match PaperMeta::get_end_of_chain(
-            &mut self._root,
-            keychain
+impl Author {
+    pub fn get_name(&self) -> String {
+        match &self.name {
+            Some(n) => n.clone(),
+            None => [&self.given, &self.family]
                 .iter()
-                .copied()
-                .take(keychain.len() - 1)
-                .map(String::from),
-        )? {
-            Yaml::Hash(vh) => {
-                vh.insert(Yaml::String(last.to_string()), value);
+                .filter_map(|p| p.as_deref())
+                .collect::<Vec<_>>()
+                .join(" "),
+        }
+    }
+
+    // `family` if there is one, otherwise the last word of the name; an old
+    //   style "Jane Doe, John Roe" string counts as just its first name
+    pub fn get_family_name(&self) -> Option<String> {
+        if let Some(f) = &self.family {
+            return Some(f.clone());
+        }
+        self.get_name()
+            .split(',')
+            .next()
+            .and_then(|first| first.split_whitespace().next_back())
+            .map(String::from)
+    }
+
+    // Markdown, one line per thing that's known about them
+    pub fn get_title_page_block(&self) -> String {
+        let mut lines = vec![self.get_name()];
+        if let Some(affiliation) = &self.affiliation {
+            lines.push(affiliation.clone());
+        }
+        if let Some(email) = &self.email {
+            lines.push(format!("<{}>", email));
+        }
+        if let Some(orcid) = &self.orcid {
+            if orcid.starts_with("http") {
+                lines.push(format!("<{}>", orcid));
+            } else {
+                lines.push(format!("<https://orcid.org/{}>", orcid));
             }
-            _ => bail!(""),
         }
+        lines.join("\\\n")
+    }
+}
+
+impl Hooks {
+    pub fn get(&self, hook: &str) -> Option<&String> {
+        match hook {
+            "on_build_success" => self.on_build_success.as_ref(),
+            "on_build_failure" => self.on_build_failure.as_ref(),
+            "on_target_reached" => self.on_target_reached.as_ref(),
+            _ => None,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.on_build_success.is_none()
+            && self.on_build_failure.is_none()
+            && self.on_target_reached.is_none()
+    }
+}
 
-        Ok(())
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn authors_from(value: Value) -> Vec<Author> {
+        serde_json::from_value::<MetaData>(json!({ "author": value }))
+            .unwrap()
+            .authors
     }
 
-    pub fn set_bool(&mut self, keychain: &[&str], value: bool) -> Result<()> {
-        self.set_node(keychain, Yaml::Boolean(value))
+    #[test]
+    fn author_as_string() {
+        let authors = authors_from(json!("Jane Doe"));
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].get_name(), "Jane Doe");
+        assert_eq!(authors[0].get_family_name().as_deref(), Some("Doe"));
     }
 
-    pub fn set_int(&mut self, keychain: &[&str], value: i64) -> Result<()> {
-        self.set_node(keychain, Yaml::Integer(value))
+    #[test]
+    fn author_as_map() {
+        let authors = authors_from(json!({
+            "given": "Jane",
+            "family": "van Doe",
+            "orcid": "0000-0001-2345-6789",
+        }));
+        assert_eq!(authors.len(), 1);
+        assert_eq!(authors[0].get_name(), "Jane van Doe");
+        assert_eq!(authors[0].get_family_name().as_deref(), Some("van Doe"));
+        assert_eq!(
+            authors[0].get_title_page_block(),
+            "Jane van Doe\\\n<https://orcid.org/0000-0001-2345-6789>"
+        );
     }
 
-    pub fn set_float(&mut self, keychain: &[&str], value: f64) -> Result<()> {
-        // yaml_rust stores floats as strings ¯\_(ツ)_/¯
-        self.set_node(keychain, Yaml::Real(value.to_string()))
+    #[test]
+    fn author_as_list() {
+        let meta: MetaData = serde_json::from_value(json!({
+            "author": ["Jane Doe", {"name": "John Roe", "family": "Roe"}, "Sam Poe"],
+        }))
+        .unwrap();
+        assert_eq!(
+            meta.get_author_names(),
+            vec!["Jane Doe", "John Roe", "Sam Poe"]
+        );
+        assert_eq!(
+            meta.get_author_string().as_deref(),
+            Some("Jane Doe, John Roe, and Sam Poe")
+        );
     }
 
-    pub fn set_string(&mut self, keychain: &[&str], value: &str) -> Result<()> {
-        self.set_node(keychain, Yaml::String(value.to_string()))
+    #[test]
+    fn author_bad_field() {
+        assert!(
+            serde_json::from_value::<MetaData>(json!({ "author": {"name": ["Jane"]} })).is_err()
+        );
     }
 
-    pub fn set_vec_string(&mut self, keychain: &[&str], value: Vec<String>) -> Result<()> {
-        let mut vecval: Vec<Yaml> = Vec::new();
-        for s in value {
-            vecval.push(Yaml::String(s));
-        }
-        let arrval = Yaml::Array(vecval);
-        self.set_node(keychain, arrval)
+    #[test]
+    fn family_name_from_old_style_string() {
+        let author = Author {
+            name: Some("Jane Q. Doe, John Roe".to_string()),
+            ..Default::default()
+        };
+        assert_eq!(author.get_family_name().as_deref(), Some("Doe"));
+        assert_eq!(Author::default().get_family_name(), None);
     }
 
-    pub fn set_null(&mut self, keychain: &[&str]) -> Result<()> {
-        self.set_node(keychain, Yaml::Null)
+    #[test]
+    fn lengths() {
+        assert_eq!(parse_length("1"), Some(1.0));
+        assert_eq!(parse_length("72pt"), Some(1.0));
+        assert_eq!(parse_length(" 2.54 cm "), Some(1.0));
+        assert_eq!(parse_length("3furlongs"), None);
     }
 }
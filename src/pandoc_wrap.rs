@@ -1,29 +1,159 @@
 use std::env;
 use std::fs;
 use std::io;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 use anyhow::{anyhow, bail, Context, Result};
-use reqwest;
+use state::InitCell;
 use tempfile::tempdir;
+use walkdir::WalkDir;
 
 use crate::config::CONFIG;
 use crate::subprocess;
+use crate::user_config;
+use crate::util;
 
-const PANDOC_LOCKED_VERSION: &str = "3.3";
+pub const PANDOC_LOCKED_VERSION: &str = "3.3";
+const PANDOC_ARCHIVE_ENV_VAR: &str = "PAPER_PANDOC_ARCHIVE";
+
+// SHA-256 of each release archive, from the checksums GitHub shows on the
+//   release page; needs updating along with PANDOC_LOCKED_VERSION, with an
+//   entry for every name get_archive_name can come up with. An archive
+//   that isn't listed here still gets installed, with a warning.
+const PANDOC_ARCHIVE_HASHES: &[(&str, &str)] = &[];
+
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum PandocSource {
+    // the pinned version, in our resources directory
+    Managed,
+    // whatever's on the PATH, because we couldn't get the pinned one
+    System,
+}
+
+impl std::fmt::Display for PandocSource {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PandocSource::Managed => write!(f, "managed"),
+            PandocSource::System => write!(f, "system"),
+        }
+    }
+}
+
+// this gets asked for a lot over the course of a build, so only figure it
+//   out once
+static PANDOC: InitCell<(PathBuf, PandocSource)> = InitCell::new();
 
 pub fn get_pandoc_exe_path() -> Result<PathBuf> {
-    // check if downloaded version exists
-    let exe_path = CONFIG
+    get_pandoc().map(|(path, _)| path)
+}
+
+fn get_pandoc() -> Result<(PathBuf, PandocSource)> {
+    if let Some(found) = PANDOC.try_get() {
+        return Ok(found.clone());
+    }
+    let found = find_pandoc()?;
+    if CONFIG.get().verbose {
+        println!("Using {} Pandoc at {:?}", found.1, found.0);
+    }
+    PANDOC.set(found.clone());
+    Ok(found)
+}
+
+fn get_managed_exe_path() -> PathBuf {
+    CONFIG
         .get()
         .resources_path
         .join(format!("pandoc-{}", PANDOC_LOCKED_VERSION))
-        .join("pandoc");
+        .join("pandoc")
+}
+
+// what a build would use right now, without downloading or installing
+//   anything to get there
+pub fn find_installed_pandoc() -> Option<(PathBuf, PandocSource)> {
+    let exe_path = get_managed_exe_path();
+    if exe_path.is_file() {
+        return Some((exe_path, PandocSource::Managed));
+    }
+    util::find_executable("pandoc").map(|p| (p, PandocSource::System))
+}
+
+fn find_pandoc() -> Result<(PathBuf, PandocSource)> {
+    // check if downloaded version exists
+    let exe_path = get_managed_exe_path();
 
     if exe_path.is_file() {
-        return Ok(exe_path);
+        return Ok((exe_path, PandocSource::Managed));
+    }
+
+    // a pre-downloaded archive, for machines without a network connection;
+    //   if one is given and doesn't work, that's an error rather than a
+    //   reason to go looking elsewhere
+    let local_archive = match env::var_os(PANDOC_ARCHIVE_ENV_VAR) {
+        Some(a) if !a.is_empty() => Some(PathBuf::from(util::expand_home(&a.to_string_lossy())?)),
+        _ => user_config::get_path(&["pandoc", "archive"])?,
+    };
+    if let Some(archive) = local_archive {
+        if !archive.is_file() {
+            bail!("Pandoc archive {:?} does not exist.", archive);
+        }
+        println!(
+            "Installing Pandoc v{} from {:?}...",
+            PANDOC_LOCKED_VERSION, archive
+        );
+        install_pandoc_archive(&archive, &exe_path)?;
+        return Ok((exe_path, PandocSource::Managed));
     }
 
+    match download_pandoc(&exe_path) {
+        Ok(()) => Ok((exe_path, PandocSource::Managed)),
+        Err(e) => {
+            let system = match util::find_executable("pandoc") {
+                Some(s) => s,
+                None => {
+                    return Err(e.context(format!(
+                        "No Pandoc available; set {} (or `pandoc.archive` in {:?}) to a downloaded Pandoc v{} release archive",
+                        PANDOC_ARCHIVE_ENV_VAR,
+                        user_config::get_user_config_path()?,
+                        PANDOC_LOCKED_VERSION
+                    )))
+                }
+            };
+            let version = get_pandoc_version(&system)?;
+            if version != PANDOC_LOCKED_VERSION {
+                return Err(e.context(format!(
+                    "Could not get Pandoc v{}, and the one at {:?} is v{}",
+                    PANDOC_LOCKED_VERSION, system, version
+                )));
+            }
+            eprintln!(
+                "[WARNING] Could not get Pandoc v{} ({}); using {:?} instead.",
+                PANDOC_LOCKED_VERSION, e, system
+            );
+            Ok((system, PandocSource::System))
+        }
+    }
+}
+
+pub fn get_pandoc_version(exe_path: &Path) -> Result<String> {
+    let output = subprocess::run_command(&exe_path.to_string_lossy(), &["--version"], None, false)
+        .with_context(|| format!("Could not run {:?}", exe_path))?;
+    // first line is like "pandoc 3.3"
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .map(|v| v.to_string())
+        .ok_or_else(|| anyhow!("Could not read version from {:?}", exe_path))
+}
+
+// whether the archive for this machine can be checked when it's installed
+pub fn has_pinned_hash() -> bool {
+    get_archive_name()
+        .map(|name| PANDOC_ARCHIVE_HASHES.iter().any(|(n, _)| *n == name))
+        .unwrap_or(false)
+}
+
+fn get_archive_name() -> Result<String> {
     // Determine the OS and architecture
     let (os_name, ext) = match env::consts::OS {
         "macos" => ("macOS", ".zip"),
@@ -31,10 +161,6 @@ pub fn get_pandoc_exe_path() -> Result<PathBuf> {
         _ => bail!("Unsupported OS!"),
     };
 
-    if os_name != "macOS" {
-        eprintln!("Not tested on Linux, but we're going to try...");
-    }
-
     let arch = match env::consts::ARCH {
         "x86_64" => {
             if os_name == "macOS" {
@@ -51,7 +177,11 @@ pub fn get_pandoc_exe_path() -> Result<PathBuf> {
         "linux" => format!("pandoc-{}-{}-{}", PANDOC_LOCKED_VERSION, os_name, arch),
         _ => unreachable!(),
     };
-    let filename = format!("{}{}", file_basename, ext);
+    Ok(format!("{}{}", file_basename, ext))
+}
+
+fn download_pandoc(exe_path: &Path) -> Result<()> {
+    let filename = get_archive_name()?;
     let dl_url = format!(
         "https://github.com/jgm/pandoc/releases/download/{}/{}",
         PANDOC_LOCKED_VERSION, filename
@@ -63,62 +193,68 @@ pub fn get_pandoc_exe_path() -> Result<PathBuf> {
     );
 
     let tmp_dir = tempdir().context("Could not create temporary directory")?;
-    let output_file_path = tmp_dir.path().join(&filename);
+    let archive_path = tmp_dir.path().join(&filename);
 
     let mut res = reqwest::blocking::get(&dl_url)
         .with_context(|| format!("Could not download `{}`", dl_url))?;
-    let mut destination = fs::File::create(&output_file_path)?;
+    if !res.status().is_success() {
+        bail!("Could not download `{}`: {}", dl_url, res.status());
+    }
+    let mut destination = fs::File::create(&archive_path)?;
     io::copy(&mut res, &mut destination)?;
 
-    // Unzip or untar the file
-    let unzipped_path = match ext {
-        ".zip" => {
-            let unzip_output = subprocess::run_command(
-                "unzip",
-                &[
-                    output_file_path.to_string_lossy().to_string(),
-                    "-d".to_string(),
-                    tmp_dir.path().to_string_lossy().to_string(),
-                ],
-                None,
-                false,
-            )
-            .context("Could not unzip downloaded Pandoc. Weird!")?;
-
-            let exe = unzip_output
-                .lines()
-                .find(|&line| {
-                    line.trim().starts_with("inflating: ") && line.trim().ends_with("/bin/pandoc")
-                })
-                .and_then(|line| line.trim().strip_prefix("inflating: "))
-                .ok_or_else(|| anyhow!("Couldn't find executable in zip"))?;
-
-            PathBuf::from(exe)
-        }
-        ".tar.gz" => {
-            let untar_output = subprocess::run_command(
-                "tar",
-                &[
-                    "-zxf",
-                    &output_file_path.to_string_lossy(),
-                    "-C",
-                    tmp_dir.path().to_string_lossy().as_ref(),
-                ],
-                None,
-                false,
-            )
-            .context("Could not untar downloaded Pandoc. Weird!")?;
-
-            let exe = untar_output
-                .lines()
-                .find(|&line| line.starts_with("x ") && line.trim().ends_with("/bin/pandoc"))
-                .and_then(|line| line.trim().strip_prefix("x "))
-                .ok_or_else(|| anyhow!("Couldn't find executable in tar"))?;
-
-            tmp_dir.path().join(exe)
-        }
-        _ => bail!("Unsupported extension: {}", ext),
-    };
+    install_pandoc_archive(&archive_path, exe_path)
+}
+
+fn install_pandoc_archive(archive_path: &Path, exe_path: &Path) -> Result<()> {
+    util::verify_download(archive_path, PANDOC_ARCHIVE_HASHES)?;
+
+    let tmp_dir = tempdir().context("Could not create temporary directory")?;
+    let archive_str = archive_path.to_string_lossy().to_string();
+    if archive_str.ends_with(".zip") {
+        subprocess::run_command(
+            "unzip",
+            &[
+                "-q".to_string(),
+                archive_str,
+                "-d".to_string(),
+                tmp_dir.path().to_string_lossy().to_string(),
+            ],
+            None,
+            false,
+        )
+        .context("Could not unzip Pandoc archive. Weird!")?;
+    } else if archive_str.ends_with(".tar.gz") || archive_str.ends_with(".tgz") {
+        subprocess::run_command(
+            "tar",
+            &[
+                "-zxf",
+                &archive_str,
+                "-C",
+                tmp_dir.path().to_string_lossy().as_ref(),
+            ],
+            None,
+            false,
+        )
+        .context("Could not untar Pandoc archive. Weird!")?;
+    } else {
+        bail!("Unsupported Pandoc archive: {:?}", archive_path);
+    }
+
+    // not relying on the archive tools to tell us what they unpacked; just
+    //   go find it
+    let unpacked_path = WalkDir::new(tmp_dir.path())
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .map(|e| e.into_path())
+        .find(|p| {
+            p.is_file()
+                && p.file_name().is_some_and(|f| f == "pandoc")
+                && p.parent()
+                    .and_then(|d| d.file_name())
+                    .is_some_and(|d| d == "bin")
+        })
+        .ok_or_else(|| anyhow!("Couldn't find executable in {:?}", archive_path))?;
 
     // Ensure the directory exists and move the file
     if let Some(parent) = exe_path.parent() {
@@ -127,8 +263,9 @@ pub fn get_pandoc_exe_path() -> Result<PathBuf> {
         bail!("No parent for exe path: {:?}", exe_path);
     }
 
-    fs::rename(&unzipped_path, &exe_path)
+    // might be on a different filesystem from the temp directory
+    fs::copy(&unpacked_path, exe_path)
         .with_context(|| format!("Failed to move Pandoc executable to {:?}", exe_path))?;
 
-    Ok(exe_path)
+    Ok(())
 }
@@ -0,0 +1,149 @@
+// A very small HTTP server for `paper watch --serve`; it only ever needs to
+//   hand a single page and a reload signal to a browser on this machine, so
+//   pulling in a whole web framework seemed like overkill.
+
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::path::PathBuf;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+
+use crate::build;
+use crate::config::CONFIG;
+use crate::metadata::PaperMeta;
+
+const KEEPALIVE_INTERVAL: Duration = Duration::from_secs(15);
+const RELOAD_SCRIPT: &str =
+    "<script>new EventSource(\"/events\").onmessage = () => location.reload();</script>";
+const PLACEHOLDER_PAGE: &str =
+    "<!DOCTYPE html>\n<html><head><title>Paper Preview</title></head><body><p>No preview has been built yet; hang tight...</p></body></html>";
+
+pub struct PreviewServer {
+    port: u16,
+    clients: Arc<Mutex<Vec<Sender<()>>>>,
+}
+
+impl PreviewServer {
+    pub fn start(port: u16) -> Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))
+            .with_context(|| format!("Could not start preview server on port {}", port))?;
+        let clients: Arc<Mutex<Vec<Sender<()>>>> = Arc::new(Mutex::new(vec![]));
+
+        let thread_clients = Arc::clone(&clients);
+        thread::spawn(move || {
+            for stream in listener.incoming().filter_map(|s| s.ok()) {
+                let conn_clients = Arc::clone(&thread_clients);
+                thread::spawn(move || {
+                    if let Err(e) = handle_connection(stream, conn_clients) {
+                        if CONFIG.get().verbose {
+                            eprintln!("[WARNING] Preview server connection error: {:?}", e);
+                        }
+                    }
+                });
+            }
+        });
+
+        Ok(PreviewServer { port, clients })
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://localhost:{}/", self.port)
+    }
+
+    // tells every connected browser to reload, forgetting any that have gone away
+    pub fn notify_reload(&self) {
+        if let Ok(mut clients) = self.clients.lock() {
+            clients.retain(|c| c.send(()).is_ok());
+        }
+    }
+}
+
+fn get_preview_path() -> Result<PathBuf> {
+    let meta = PaperMeta::new()?;
+    let filename = build::get_output_filename(&meta)?;
+    Ok(PathBuf::from(&CONFIG.get().output_directory_name).join(format!("{}.html", filename)))
+}
+
+fn get_page() -> String {
+    let page = get_preview_path()
+        .and_then(|p| fs::read_to_string(&p).with_context(|| format!("Could not read {:?}", p)))
+        .unwrap_or(PLACEHOLDER_PAGE.to_string());
+
+    // injected here rather than at build time so the built file stays clean
+    match page.rfind("</body>") {
+        Some(idx) => format!("{}{}\n{}", &page[..idx], RELOAD_SCRIPT, &page[idx..]),
+        None => format!("{}\n{}", page, RELOAD_SCRIPT),
+    }
+}
+
+fn handle_connection(mut stream: TcpStream, clients: Arc<Mutex<Vec<Sender<()>>>>) -> Result<()> {
+    let mut reader = BufReader::new(stream.try_clone().context("Could not clone stream")?);
+    let mut request_line = String::new();
+    reader
+        .read_line(&mut request_line)
+        .context("Could not read request")?;
+    // drain the headers; we don't care about any of them
+    loop {
+        let mut header = String::new();
+        let read = reader
+            .read_line(&mut header)
+            .context("Could not read request headers")?;
+        if read == 0 || header.trim().is_empty() {
+            break;
+        }
+    }
+
+    let path = request_line.split_whitespace().nth(1).unwrap_or("/");
+    match path {
+        "/" | "/index.html" => {
+            let body = get_page();
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nCache-Control: no-store\r\nConnection: close\r\n\r\n{}",
+                body.len(),
+                body
+            )
+            .context("Could not write response")?;
+        }
+        "/events" => {
+            write!(
+                stream,
+                "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-store\r\nConnection: keep-alive\r\n\r\n"
+            )
+            .context("Could not write response")?;
+            stream.flush().context("Could not flush response")?;
+
+            let (tx, rx) = mpsc::channel();
+            clients
+                .lock()
+                .map_err(|_| anyhow::anyhow!("Preview client list is poisoned"))?
+                .push(tx);
+
+            loop {
+                let msg = match rx.recv_timeout(KEEPALIVE_INTERVAL) {
+                    Ok(()) => "data: reload\n\n",
+                    // also how we notice the browser has gone away
+                    Err(RecvTimeoutError::Timeout) => ": keepalive\n\n",
+                    Err(RecvTimeoutError::Disconnected) => break,
+                };
+                if stream.write_all(msg.as_bytes()).is_err() || stream.flush().is_err() {
+                    break;
+                }
+            }
+        }
+        _ => {
+            write!(
+                stream,
+                "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+            )
+            .context("Could not write response")?;
+        }
+    }
+
+    Ok(())
+}
@@ -1,16 +1,20 @@
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::io::Write;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
 use walkdir::WalkDir;
 use yaml_rust::{yaml, Yaml, YamlEmitter};
 
 use crate::config::CONFIG;
+use crate::import;
 use crate::subprocess;
 use crate::util;
 
-pub fn init_project() -> Result<()> {
+pub fn init_project(import_from: Option<&Path>) -> Result<()> {
     let proj_path_buf = std::env::current_dir().context("Current path is invalid.")?;
     if proj_path_buf
         .read_dir()
@@ -45,24 +49,26 @@ pub fn init_project() -> Result<()> {
         }
     }
 
+    let imported_meta = match import_from {
+        Some(source) => Some(import::import_document(source)?),
+        None => None,
+    };
+
     let mut meta_chain: Vec<Yaml> = Vec::new();
     let mut current_path_option: Option<&Path> = Some(proj_path_buf.as_path());
     while let Some(current_path) = current_path_option {
-        match util::find_meta(Some(current_path)) {
-            Ok(meta_path) => {
-                if CONFIG.get().verbose {
-                    println!("Found meta file at {:?}", meta_path);
-                }
-                match util::load_yml_file(&meta_path) {
-                    Ok(meta_yml) => match meta_yml {
-                        Yaml::Hash(_) => meta_chain.push(meta_yml),
-                        _ => bail!("Non-hash YAML document found at {:?}", meta_path),
-                    },
-                    // don't give up on one bad YAML file; just print the error and skip it
-                    Err(e) => eprintln!("ERROR: {}", e),
-                }
+        if let Ok(meta_path) = util::find_meta(Some(current_path)) {
+            if CONFIG.get().verbose {
+                println!("Found meta file at {:?}", meta_path);
+            }
+            match util::load_yml_file(&meta_path) {
+                Ok(meta_yml) => match meta_yml {
+                    Yaml::Hash(_) => meta_chain.push(meta_yml),
+                    _ => bail!("Non-hash YAML document found at {:?}", meta_path),
+                },
+                // don't give up on one bad YAML file; just print the error and skip it
+                Err(e) => eprintln!("ERROR: {}", e),
             }
-            Err(_) => {}
         }
         current_path_option = current_path.parent();
     }
@@ -73,6 +79,11 @@ pub fn init_project() -> Result<()> {
         // already checked that everything is a hash, so this unwrap is safe
         util::merge_yaml_hash(&mut meta, &m.into_hash().unwrap());
     }
+    // what came from the imported document is more specific than anything
+    //   up the tree
+    if let Some(im) = imported_meta {
+        util::merge_yaml_hash(&mut meta, &im);
+    }
 
     let mut meta_str = String::new();
     let mut yaml_emitter = YamlEmitter::new(&mut meta_str);
@@ -87,6 +98,8 @@ pub fn init_project() -> Result<()> {
 
     fs::create_dir("research").context("Could not write research directory.")?;
 
+    write_resources_manifest(&get_resource_hashes(Path::new(RESOURCES_DIRECTORY))?)?;
+
     subprocess::run_command("git", &["init"], None, false)?;
     subprocess::run_command("git", &["add", "."], None, false)?;
     subprocess::run_command(
@@ -106,11 +119,20 @@ pub fn init_project() -> Result<()> {
     Ok(())
 }
 
-pub fn new_project(project_name: &str) -> Result<()> {
+pub fn new_project(project_name: &str, import_from: Option<&Path>) -> Result<()> {
     let project_path = Path::new(project_name);
     if project_path.exists() {
         bail!("Project path already exists: {}", project_name);
     }
+    // has to be resolved before we move into the new directory
+    let import_from = match import_from {
+        Some(source) => Some(
+            source
+                .canonicalize()
+                .with_context(|| format!("Could not find {:?}", source))?,
+        ),
+        None => None,
+    };
 
     println!("Starting new project called '{}'...", project_name);
     fs::create_dir(project_path)
@@ -118,7 +140,7 @@ pub fn new_project(project_name: &str) -> Result<()> {
     std::env::set_current_dir(project_path)
         .with_context(|| format!("Could not move to directory {:?}", project_path))?;
 
-    init_project()
+    init_project(import_from.as_deref())
 }
 
 pub fn dev() -> Result<()> {
@@ -172,3 +194,299 @@ pub fn dev() -> Result<()> {
 
     Ok(())
 }
+
+const RESOURCES_DIRECTORY: &str = ".paper_resources";
+const RESOURCES_MANIFEST_FILENAME: &str = "resources_manifest.json";
+
+fn hash_bytes(datums: &[u8]) -> String {
+    format!("{:x}", Sha256::digest(datums))
+}
+
+// relative path => hash of every file under the directory
+fn get_resource_hashes(base: &Path) -> Result<BTreeMap<String, String>> {
+    let mut hashes = BTreeMap::new();
+    if !base.exists() {
+        return Ok(hashes);
+    }
+    for entry in WalkDir::new(base).into_iter().filter_map(|e| e.ok()) {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let rel = entry
+            .path()
+            .strip_prefix(base)
+            .context("Could not strip prefix")?
+            .to_string_lossy()
+            .to_string();
+        let datums =
+            fs::read(entry.path()).with_context(|| format!("Could not read {:?}", entry.path()))?;
+        hashes.insert(rel, hash_bytes(&datums));
+    }
+    Ok(hashes)
+}
+
+fn get_manifest_path() -> Result<PathBuf> {
+    Ok(std::env::current_dir()
+        .context("Could not get current directory")?
+        .join(".paper_data")
+        .join(RESOURCES_MANIFEST_FILENAME))
+}
+
+// remembers what the resources looked like when paper put them there, so a
+//   later update can tell a stale file from one that's been edited locally
+fn write_resources_manifest(hashes: &BTreeMap<String, String>) -> Result<()> {
+    let manifest_path = get_manifest_path()?;
+    if let Some(parent) = manifest_path.parent() {
+        fs::create_dir_all(parent)
+            .with_context(|| format!("Could not create directory path {:?}", parent))?;
+    }
+    let manifest = json!({
+        "paper_version": util::LIB_VERSION,
+        "files": hashes,
+    });
+    fs::write(
+        &manifest_path,
+        serde_json::to_string_pretty(&manifest)
+            .context("Could not make pretty string from manifest JSON")?,
+    )
+    .with_context(|| format!("Could not write {:?}", manifest_path))
+}
+
+fn read_resources_manifest() -> Result<Option<BTreeMap<String, String>>> {
+    let manifest_path = get_manifest_path()?;
+    if !manifest_path.exists() {
+        return Ok(None);
+    }
+    let manifest_text = fs::read_to_string(&manifest_path)
+        .with_context(|| format!("Could not read {:?}", manifest_path))?;
+    let manifest: Value = serde_json::from_str(&manifest_text)
+        .with_context(|| format!("Could not parse {:?}", manifest_path))?;
+    let mut hashes = BTreeMap::new();
+    if let Some(Value::Object(files)) = manifest.get("files") {
+        for (k, v) in files {
+            if let Some(h) = v.as_str() {
+                hashes.insert(k.clone(), h.to_string());
+            }
+        }
+    }
+    Ok(Some(hashes))
+}
+
+// rough count of lines added and removed, good enough for a summary
+fn get_line_changes(old: &[u8], new: &[u8]) -> Option<(usize, usize)> {
+    let old = std::str::from_utf8(old).ok()?;
+    let new = std::str::from_utf8(new).ok()?;
+    let mut old_counts: HashMap<&str, i64> = HashMap::new();
+    for line in old.lines() {
+        *old_counts.entry(line).or_default() += 1;
+    }
+    let mut added = 0;
+    for line in new.lines() {
+        match old_counts.get_mut(line) {
+            Some(c) if *c > 0 => *c -= 1,
+            _ => added += 1,
+        }
+    }
+    let removed = old_counts.values().filter(|c| **c > 0).sum::<i64>() as usize;
+    Some((added, removed))
+}
+
+// what each release put in .paper_resources, for projects that predate the
+//   manifest (or whose files a build hasn't touched since)
+const RELEASE_RESOURCE_HASHES: &str = include_str!("../resources/release_resource_hashes.txt");
+
+fn parse_version(version: &str) -> Vec<u64> {
+    version
+        .split('.')
+        .map(|n| n.parse::<u64>().unwrap_or_default())
+        .collect()
+}
+
+// relative path => every hash it shipped with, up to and including the given
+//   version; that's whatever stamped last_paper_version.txt, and since every
+//   build restamps it, the resources may well be from an older release
+fn get_release_hashes(version: Option<&str>) -> HashMap<String, Vec<String>> {
+    let limit = version.map(parse_version);
+    let mut hashes: HashMap<String, Vec<String>> = HashMap::new();
+    for line in RELEASE_RESOURCE_HASHES.lines() {
+        if line.starts_with('#') {
+            continue;
+        }
+        let mut parts = line.splitn(3, ' ');
+        let (release, hash, rel) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(r), Some(h), Some(p)) => (r, h, p),
+            _ => continue,
+        };
+        if limit.as_ref().is_some_and(|l| parse_version(release) > *l) {
+            continue;
+        }
+        hashes
+            .entry(rel.to_string())
+            .or_default()
+            .push(hash.to_string());
+    }
+    hashes
+}
+
+#[derive(PartialEq)]
+enum ResourceChange {
+    Added,
+    Updated,
+    LocallyModified,
+}
+
+pub fn update_resources(force: bool) -> Result<()> {
+    util::ensure_paper_dir()?;
+
+    let local_path = Path::new(RESOURCES_DIRECTORY);
+    if local_path.is_symlink() {
+        bail!(
+            "{:?} is symlinked for dev work; it's already as new as it gets.",
+            local_path
+        );
+    }
+    let template_path = CONFIG
+        .get()
+        .resources_path
+        .join("project_template")
+        .join(RESOURCES_DIRECTORY);
+
+    let last_stamp = fs::read_to_string(Path::new(".paper_data").join("last_paper_version.txt"))
+        .ok()
+        .and_then(|v| v.lines().next().map(|l| l.to_string()));
+    println!(
+        "Project was last touched by {}; installed is {} v{}.",
+        last_stamp.as_deref().unwrap_or("an unknown version"),
+        util::LIB_NAME,
+        util::LIB_VERSION
+    );
+    // first line is like "SJML Paper v0.6.0"
+    let last_version = last_stamp
+        .as_deref()
+        .and_then(|s| s.strip_prefix(&format!("{} v", util::LIB_NAME)));
+    let release_hashes = get_release_hashes(last_version);
+
+    let template_hashes = get_resource_hashes(&template_path)?;
+    let local_hashes = get_resource_hashes(local_path)?;
+    let manifest = read_resources_manifest()?;
+    if manifest.is_none() && CONFIG.get().verbose {
+        println!("No resources manifest; going by what past releases shipped.");
+    }
+
+    let mut changes = vec![];
+    for (rel, template_hash) in &template_hashes {
+        let change = match local_hashes.get(rel) {
+            None => ResourceChange::Added,
+            Some(local_hash) if local_hash == template_hash => continue,
+            // only safe to replace if it's still what paper put there
+            Some(local_hash) => {
+                let installed = manifest
+                    .as_ref()
+                    .and_then(|m| m.get(rel))
+                    .is_some_and(|h| h == local_hash);
+                let released = release_hashes
+                    .get(rel)
+                    .is_some_and(|hs| hs.contains(local_hash));
+                if installed || released {
+                    ResourceChange::Updated
+                } else {
+                    ResourceChange::LocallyModified
+                }
+            }
+        };
+        changes.push((rel.clone(), change));
+    }
+
+    if changes.is_empty() {
+        println!("Resources are already up to date.");
+        return Ok(());
+    }
+
+    let mut to_copy = vec![];
+    for (rel, change) in &changes {
+        let template_file = template_path.join(rel);
+        let local_file = local_path.join(rel);
+        let new_datums = fs::read(&template_file)
+            .with_context(|| format!("Could not read {:?}", template_file))?;
+        let summary = match change {
+            ResourceChange::Added => "new file".to_string(),
+            _ => {
+                let old_datums = fs::read(&local_file)
+                    .with_context(|| format!("Could not read {:?}", local_file))?;
+                match get_line_changes(&old_datums, &new_datums) {
+                    Some((added, removed)) => format!("+{} -{} lines", added, removed),
+                    None => format!("{} => {} bytes", old_datums.len(), new_datums.len()),
+                }
+            }
+        };
+        let action = match change {
+            ResourceChange::Added => "add",
+            ResourceChange::Updated => "update",
+            ResourceChange::LocallyModified => {
+                if force {
+                    "replace (locally modified)"
+                } else {
+                    "keep (locally modified)"
+                }
+            }
+        };
+        println!("\t{:<28}{} ({})", action, rel, summary);
+        if *change != ResourceChange::LocallyModified || force {
+            to_copy.push((template_file, local_file));
+        }
+    }
+
+    let kept = changes.len() - to_copy.len();
+    if kept > 0 {
+        println!(
+            "Kept {} locally modified file(s); run again with `--force` to replace them.",
+            kept
+        );
+    }
+    if to_copy.is_empty() {
+        return Ok(());
+    }
+
+    for (src, dst) in &to_copy {
+        if let Some(parent) = dst.parent() {
+            fs::create_dir_all(parent)
+                .with_context(|| format!("Could not create directory path {:?}", parent))?;
+        }
+        fs::copy(src, dst).with_context(|| format!("Could not copy file {:?}", src))?;
+    }
+
+    // the manifest tracks what paper installed, so kept files keep their
+    //   old entries and still count as modified next time
+    let mut installed = manifest.unwrap_or_default();
+    for (rel, _) in changes
+        .iter()
+        .filter(|(_, c)| *c != ResourceChange::LocallyModified || force)
+    {
+        installed.insert(rel.clone(), template_hashes[rel].clone());
+    }
+    write_resources_manifest(&installed)?;
+    util::stamp_local_dir()?;
+    println!("Updated {} file(s).", to_copy.len());
+
+    // only what this touched; anything else the user has staged stays put
+    if Path::new(".git").exists() {
+        let manifest_path = format!(".paper_data/{}", RESOURCES_MANIFEST_FILENAME);
+        let paths = [
+            RESOURCES_DIRECTORY,
+            &manifest_path,
+            ".paper_data/last_paper_version.txt",
+        ];
+        let mut add_args = vec!["add", "--"];
+        add_args.extend(paths);
+        subprocess::run_command("git", &add_args, None, false)?;
+        let message = format!(
+            "Update paper resources\n---\n{}",
+            util::get_paper_version_stamp()
+        );
+        let mut commit_args = vec!["commit", "-m", &message, "--"];
+        commit_args.extend(paths);
+        subprocess::run_command("git", &commit_args, None, false)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,192 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+use anyhow::{bail, Context, Result};
+use serde_json::{self, Value};
+
+use crate::config::CONFIG;
+use crate::metadata::PaperMeta;
+use crate::util;
+
+const BIB_STATE_FILENAME: &str = "bibliography_state.json";
+pub const DEFAULT_PROJECT_BIBLIOGRAPHY: &str = "references.json";
+
+// the `sources` list from the meta file, with home directories expanded
+pub fn get_bibliography_paths(meta: &PaperMeta) -> Result<Vec<String>> {
+    let mut paths = vec![];
+    for bs in &meta.sources {
+        paths.push(util::expand_home(bs)?);
+    }
+    Ok(paths)
+}
+
+fn get_state_path() -> Result<PathBuf> {
+    Ok(std::env::current_dir()
+        .context("Could not get current directory")?
+        .join(".paper_data")
+        .join(BIB_STATE_FILENAME))
+}
+
+pub fn get_file_stamp(path: &Path) -> Result<Value> {
+    let md =
+        fs::metadata(path).with_context(|| format!("Could not get metadata for {:?}", path))?;
+    let modified = md
+        .modified()
+        .with_context(|| format!("Could not get modification time for {:?}", path))?
+        .duration_since(UNIX_EPOCH)
+        .context("Invalid modification time or *very* old file.")?;
+
+    let mut stamp = serde_json::Map::new();
+    stamp.insert(
+        "modified".to_string(),
+        Value::Number(serde_json::Number::from(modified.as_secs())),
+    );
+    stamp.insert(
+        "size".to_string(),
+        Value::Number(serde_json::Number::from(md.len())),
+    );
+    Ok(Value::Object(stamp))
+}
+
+fn load_state() -> Result<serde_json::Map<String, Value>> {
+    let state_path = get_state_path()?;
+    if !state_path.exists() {
+        return Ok(serde_json::Map::new());
+    }
+    let state_text = fs::read_to_string(&state_path)
+        .with_context(|| format!("Could not read {:?}", &state_path))?;
+    match serde_json::from_str(&state_text) {
+        Ok(Value::Object(state)) => Ok(state),
+        // a mangled state file just means everything looks new
+        _ => Ok(serde_json::Map::new()),
+    }
+}
+
+// returns the bibliography paths whose files differ from what was
+//   recorded at the end of the last build
+pub fn get_changed_bibliographies(meta: &PaperMeta) -> Result<Vec<String>> {
+    let state = load_state()?;
+    if state.is_empty() {
+        // never built (or built before we kept track), so nothing to compare against
+        return Ok(vec![]);
+    }
+
+    let mut changed = vec![];
+    for bp in get_bibliography_paths(meta)? {
+        let bpp = Path::new(&bp);
+        if !bpp.exists() {
+            continue;
+        }
+        let stamp = get_file_stamp(bpp)?;
+        if state.get(&bp) != Some(&stamp) {
+            changed.push(bp);
+        }
+    }
+    Ok(changed)
+}
+
+pub fn record_bibliography_state(meta: &PaperMeta) -> Result<()> {
+    let mut state = serde_json::Map::new();
+    for bp in get_bibliography_paths(meta)? {
+        let bpp = Path::new(&bp);
+        if bpp.exists() {
+            state.insert(bp.clone(), get_file_stamp(bpp)?);
+        }
+    }
+
+    let state_str = serde_json::to_string_pretty(&Value::Object(state))
+        .context("Could not make pretty string from bibliography state")?;
+    let state_path = get_state_path()?;
+    fs::write(&state_path, state_str)
+        .with_context(|| format!("Could not write {:?}", &state_path))?;
+
+    Ok(())
+}
+
+fn fetch_source(source: &str) -> Result<Vec<u8>> {
+    if source.starts_with("http://") || source.starts_with("https://") {
+        if CONFIG.get().verbose {
+            println!("Fetching references from {}...", source);
+        }
+        let res = reqwest::blocking::get(source)
+            .with_context(|| format!("Could not connect to `{}`; is Zotero running?", source))?;
+        if !res.status().is_success() {
+            bail!(
+                "Request to `{}` failed with status {}",
+                source,
+                res.status()
+            );
+        }
+        let body = res
+            .bytes()
+            .with_context(|| format!("Could not read response from `{}`", source))?;
+        Ok(body.to_vec())
+    } else {
+        let local = util::expand_home(source)?;
+        if CONFIG.get().verbose {
+            println!("Reading references from {}...", local);
+        }
+        fs::read(&local).with_context(|| format!("Could not read reference export at {}", local))
+    }
+}
+
+pub fn sync() -> Result<()> {
+    util::ensure_paper_dir()?;
+
+    let meta = PaperMeta::new()?;
+
+    let source = match meta.refs.sync_from.clone() {
+        Some(s) => s,
+        None => bail!("No `refs.sync_from` set in the meta file; nowhere to sync references from."),
+    };
+    let target = util::expand_home(
+        meta.refs
+            .bibliography
+            .as_deref()
+            .unwrap_or(DEFAULT_PROJECT_BIBLIOGRAPHY),
+    )?;
+
+    let mut datums = fetch_source(&source)?;
+
+    if Path::new(&target).extension().unwrap_or_default() == "json" {
+        let parsed: Value = serde_json::from_slice(&datums)
+            .with_context(|| format!("Could not parse JSON from {}", source))?;
+        // JSON-RPC style endpoints wrap their payload in a `result` field
+        let refs = match parsed {
+            Value::Object(mut obj) if obj.contains_key("result") => obj.remove("result").unwrap(),
+            other => other,
+        };
+        match &refs {
+            Value::Array(entries) => {
+                println!("Found {} references.", entries.len());
+            }
+            _ => bail!("Expected CSL JSON array from {}", source),
+        }
+        datums = serde_json::to_string_pretty(&refs)
+            .context("Could not make pretty string from reference JSON")?
+            .into_bytes();
+    }
+
+    let target_path = Path::new(&target);
+    if target_path.exists() {
+        let existing =
+            fs::read(target_path).with_context(|| format!("Could not read {:?}", target_path))?;
+        if existing == datums {
+            println!("Bibliography `{}` is already up to date.", target);
+            return Ok(());
+        }
+    }
+    fs::write(target_path, datums).with_context(|| format!("Could not write {:?}", target_path))?;
+    println!("Updated `{}` from {}.", target, source);
+
+    // `sources` get compared with their home directories expanded too
+    if !get_bibliography_paths(&meta)?.contains(&target) {
+        eprintln!(
+            "[WARNING] `{}` is not listed in `sources`, so builds won't use it.",
+            target
+        );
+    }
+
+    Ok(())
+}
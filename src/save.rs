@@ -35,7 +35,7 @@ pub fn save(msg: Option<&String>) -> Result<()> {
     if !readme_path.exists() {
         let mut readme_file =
             fs::File::create(&readme_path).context("Could not create readme path")?;
-        match meta.get_string(&["data", "class_mnemonic"]) {
+        match &meta.data.class_mnemonic {
             Some(mnemonic) => {
                 writeln!(readme_file, "# {}: {}\n", mnemonic, util::get_assignment()?)
                     .context("Could not write to readme file")?;
@@ -56,9 +56,10 @@ pub fn save(msg: Option<&String>) -> Result<()> {
     let readme_meta_start_idx = readme_text.find(METADATA_START_SENTINEL);
     let readme_meta_end_idx = readme_text.find(METADATA_END_SENTINEL);
 
-    if readme_meta_start_idx.is_some() && readme_meta_end_idx.is_some() {
-        let readme_meta_start_idx = readme_meta_start_idx.unwrap();
-        let readme_meta_end_idx = readme_meta_end_idx.unwrap() + METADATA_END_SENTINEL.len();
+    if let (Some(readme_meta_start_idx), Some(readme_meta_end_idx)) =
+        (readme_meta_start_idx, readme_meta_end_idx)
+    {
+        let readme_meta_end_idx = readme_meta_end_idx + METADATA_END_SENTINEL.len();
 
         let readme_before = &readme_text[0..readme_meta_start_idx];
         let readme_after = &readme_text[readme_meta_end_idx..];
@@ -97,9 +98,7 @@ pub fn push() -> Result<()> {
     if remote.is_empty() {
         let meta = PaperMeta::new()?;
         // default_repo = f"{meta['data']['class_mnemonic'].replace(' ', '')} {get_assignment()}"
-        let mnemonic = meta
-            .get_string(&["data", "class_mnemonic"])
-            .unwrap_or_default();
+        let mnemonic = meta.data.class_mnemonic.unwrap_or_default();
         let default_name = format!("{} {}", mnemonic, util::get_assignment()?);
         let default_name = default_name.trim();
 
@@ -150,21 +149,8 @@ pub fn web() -> Result<()> {
 fn get_progress_image_str(meta: &PaperMeta) -> Result<String> {
     let mut img = String::new();
 
-    let target_wc = meta.get_int(&["target_word_count"]).unwrap_or(-1);
-    let due_date = match meta.get_string(&["data", "date"]) {
-        None => None,
-        Some(ds) => {
-            let nd = NaiveDate::parse_from_str(&ds, "%Y-%m-%d")
-                .with_context(|| format!("Could not parse DateTime {}", &ds))?;
-            let dd = nd
-                .and_time(NaiveTime::default())
-                .and_local_timezone(Utc)
-                .single()
-                .with_context(|| format!("Could not add time to parsed Datetime {}", &ds))
-                .unwrap();
-            Some(dd)
-        }
-    };
+    let target_wc = meta.target_word_count.unwrap_or(-1);
+    let due_date = util::get_due_date(meta)?.map(|dd| dd.instant.with_timezone(&Utc));
 
     let mut commits = get_commit_data()?;
     commits.reverse();
@@ -245,7 +231,7 @@ fn get_progress_image_str(meta: &PaperMeta) -> Result<String> {
         let wcu: usize = target_wc
             .try_into()
             .context("Could not convert target_wc")?;
-        let wordcount_series = vec![(earliest, wcu), (latest, wcu)];
+        let wordcount_series = [(earliest, wcu), (latest, wcu)];
         chart
             .draw_series(LineSeries::new(
                 wordcount_series.iter().map(|(dt, wc)| (*dt, *wc)),
@@ -255,7 +241,7 @@ fn get_progress_image_str(meta: &PaperMeta) -> Result<String> {
     }
 
     if let Some(due_date) = due_date {
-        let duedate_series = vec![(due_date, min_wc), (due_date, max_wc)];
+        let duedate_series = [(due_date, min_wc), (due_date, max_wc)];
         chart
             .draw_series(LineSeries::new(
                 duedate_series.iter().map(|(dt, wc)| (*dt, *wc)),
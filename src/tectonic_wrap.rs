@@ -0,0 +1,238 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use anyhow::{anyhow, bail, Context, Result};
+use tempfile::tempdir;
+
+use crate::config::CONFIG;
+use crate::subprocess;
+use crate::user_config;
+use crate::util;
+
+pub const TECTONIC_LOCKED_VERSION: &str = "0.15.0";
+
+// SHA-256 of each release archive, from the checksums GitHub shows on the
+//   release page; needs updating along with TECTONIC_LOCKED_VERSION, with an
+//   entry for every OS and architecture get_archive_name knows about. An
+//   archive that isn't listed here still gets installed, with a warning.
+const TECTONIC_ARCHIVE_HASHES: &[(&str, &str)] = &[];
+
+// left next to where the executable would go when a download doesn't match
+//   its checksum, so every PDF build doesn't fetch the same bad archive again
+const FAILED_DOWNLOAD_MARKER: &str = "download_failed.txt";
+
+fn get_managed_exe_path() -> PathBuf {
+    CONFIG
+        .get()
+        .resources_path
+        .join(format!("tectonic-{}", TECTONIC_LOCKED_VERSION))
+        .join("tectonic")
+}
+
+fn get_configured_exe_path() -> Result<Option<PathBuf>> {
+    match user_config::get_path(&["tectonic", "path"])? {
+        Some(configured) => {
+            if !configured.is_file() {
+                bail!(
+                    "Tectonic path from user config ({:?}) does not exist.",
+                    configured
+                );
+            }
+            Ok(Some(configured))
+        }
+        None => Ok(None),
+    }
+}
+
+fn get_failed_download_marker() -> PathBuf {
+    get_managed_exe_path().with_file_name(FAILED_DOWNLOAD_MARKER)
+}
+
+// why the last download was thrown out, if it was
+pub fn get_failed_download() -> Option<String> {
+    fs::read_to_string(get_failed_download_marker()).ok()
+}
+
+// whether the archive for this machine can be checked when it's installed
+pub fn has_pinned_hash() -> bool {
+    get_archive_name()
+        .map(|name| TECTONIC_ARCHIVE_HASHES.iter().any(|(n, _)| *n == name))
+        .unwrap_or(false)
+}
+
+// same preference as below, but without downloading anything
+pub fn find_installed_tectonic() -> Result<Option<PathBuf>> {
+    if let Some(configured) = get_configured_exe_path()? {
+        return Ok(Some(configured));
+    }
+    let exe_path = get_managed_exe_path();
+    if exe_path.is_file() {
+        return Ok(Some(exe_path));
+    }
+    Ok(util::find_executable("tectonic"))
+}
+
+// in order of preference: whatever the user config points at, the copy we
+//   manage, and then (if we can't download that) whatever's on the PATH
+pub fn get_tectonic_exe_path() -> Result<PathBuf> {
+    if let Some(configured) = get_configured_exe_path()? {
+        return Ok(configured);
+    }
+
+    let exe_path = get_managed_exe_path();
+    if exe_path.is_file() {
+        return Ok(exe_path);
+    }
+
+    match download_tectonic(&exe_path) {
+        Ok(()) => Ok(exe_path),
+        Err(e) => {
+            let system = match util::find_executable("tectonic") {
+                Some(s) => s,
+                None => {
+                    return Err(e.context(format!(
+                        "No Tectonic available; set `tectonic.path` in {:?} to use one you already have.",
+                        user_config::get_user_config_path()?
+                    )))
+                }
+            };
+            let version = get_tectonic_version(&system)?;
+            if version != TECTONIC_LOCKED_VERSION {
+                return Err(e.context(format!(
+                    "Could not get Tectonic v{}, and the one at {:?} is v{}",
+                    TECTONIC_LOCKED_VERSION, system, version
+                )));
+            }
+            eprintln!(
+                "[WARNING] Could not get Tectonic v{} ({}); using {:?} instead.",
+                TECTONIC_LOCKED_VERSION, e, system
+            );
+            Ok(system)
+        }
+    }
+}
+
+pub fn get_tectonic_version(exe_path: &Path) -> Result<String> {
+    let output = subprocess::run_command(&exe_path.to_string_lossy(), &["--version"], None, false)
+        .with_context(|| format!("Could not run {:?}", exe_path))?;
+    // first line is like "Tectonic 0.15.0"
+    output
+        .lines()
+        .next()
+        .and_then(|line| line.split_whitespace().nth(1))
+        .map(|v| v.to_string())
+        .ok_or_else(|| anyhow!("Could not read version from {:?}", exe_path))
+}
+
+// lets the build run with no network: either a bundle that's been
+//   downloaded ahead of time, or just whatever tectonic has cached
+pub fn get_bundle_args() -> Result<Vec<String>> {
+    let mut args = vec![];
+    if let Some(bundle) = user_config::get_path(&["tectonic", "bundle"])? {
+        if !bundle.exists() {
+            bail!(
+                "Tectonic bundle from user config ({:?}) does not exist.",
+                bundle
+            );
+        }
+        if CONFIG.get().verbose {
+            println!("Using local Tectonic bundle at {:?}", bundle);
+        }
+        args.extend_from_slice(&["--bundle".to_string(), bundle.to_string_lossy().to_string()]);
+    }
+    if user_config::get_bool(&["tectonic", "only_cached"])?.unwrap_or(false) {
+        args.push("--only-cached".to_string());
+    }
+    Ok(args)
+}
+
+fn get_archive_name() -> Result<String> {
+    let os_name = match env::consts::OS {
+        "macos" => "apple-darwin",
+        "linux" => "unknown-linux-musl",
+        _ => bail!("Unsupported OS!"),
+    };
+    let arch = match env::consts::ARCH {
+        "x86_64" => "x86_64",
+        "aarch64" => "aarch64",
+        _ => bail!("Unsupported architecture!"),
+    };
+    Ok(format!(
+        "tectonic-{}-{}-{}.tar.gz",
+        TECTONIC_LOCKED_VERSION, arch, os_name
+    ))
+}
+
+fn download_tectonic(exe_path: &Path) -> Result<()> {
+    let marker = get_failed_download_marker();
+    if let Some(reason) = get_failed_download() {
+        bail!(
+            "An earlier download of Tectonic v{} was thrown out ({}); delete {:?} to try again.",
+            TECTONIC_LOCKED_VERSION,
+            reason.trim(),
+            marker
+        );
+    }
+
+    let filename = get_archive_name()?;
+    let dl_url = format!(
+        "https://github.com/tectonic-typesetting/tectonic/releases/download/tectonic%40{}/{}",
+        TECTONIC_LOCKED_VERSION, filename
+    );
+
+    println!(
+        "Attempting to download Tectonic v{}...",
+        TECTONIC_LOCKED_VERSION
+    );
+
+    let tmp_dir = tempdir().context("Could not create temporary directory")?;
+    let archive_path = tmp_dir.path().join(&filename);
+
+    let mut res = reqwest::blocking::get(&dl_url)
+        .with_context(|| format!("Could not download `{}`", dl_url))?;
+    if !res.status().is_success() {
+        bail!("Could not download `{}`: {}", dl_url, res.status());
+    }
+    let mut destination = fs::File::create(&archive_path)?;
+    io::copy(&mut res, &mut destination)?;
+    if let Err(e) = util::verify_download(&archive_path, TECTONIC_ARCHIVE_HASHES) {
+        if let Some(parent) = marker.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(&marker, format!("{:#}\n", e))
+            .with_context(|| format!("Could not write {:?}", marker))?;
+        return Err(e);
+    }
+
+    // the release archives are just the executable
+    subprocess::run_command(
+        "tar",
+        &[
+            "-zxf",
+            &archive_path.to_string_lossy(),
+            "-C",
+            tmp_dir.path().to_string_lossy().as_ref(),
+        ],
+        None,
+        false,
+    )
+    .context("Could not untar downloaded Tectonic. Weird!")?;
+    let unpacked_path = tmp_dir.path().join("tectonic");
+    if !unpacked_path.is_file() {
+        bail!("Couldn't find executable in tar");
+    }
+
+    if let Some(parent) = exe_path.parent() {
+        fs::create_dir_all(parent)?;
+    } else {
+        bail!("No parent for exe path: {:?}", exe_path);
+    }
+
+    // might be on a different filesystem from the temp directory
+    fs::copy(&unpacked_path, exe_path)
+        .with_context(|| format!("Failed to move Tectonic executable to {:?}", exe_path))?;
+
+    Ok(())
+}
@@ -0,0 +1,66 @@
+// Settings that belong to the person running paper rather than to any one
+//   project (where their tools and offline bundles live, that sort of
+//   thing), kept in `$XDG_CONFIG_HOME/paper/config.yml`, or
+//   `~/.config/paper/config.yml` if that isn't set.
+
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use yaml_rust::Yaml;
+
+use crate::util;
+
+pub fn get_user_config_path() -> Result<PathBuf> {
+    let config_home = match std::env::var_os("XDG_CONFIG_HOME") {
+        Some(xdg) if !xdg.is_empty() => PathBuf::from(xdg),
+        _ => PathBuf::from(std::env::var("HOME").context("Could not get $HOME env var")?)
+            .join(".config"),
+    };
+    Ok(config_home.join("paper").join("config.yml"))
+}
+
+fn load_user_config() -> Result<Option<Yaml>> {
+    let config_path = get_user_config_path()?;
+    if !config_path.exists() {
+        return Ok(None);
+    }
+    let config = util::load_yml_file(&config_path)?;
+    match config {
+        Yaml::Hash(_) => Ok(Some(config)),
+        Yaml::Null => Ok(None),
+        _ => bail!("Non-hash YAML document found at {:?}", config_path),
+    }
+}
+
+fn get_setting(keychain: &[&str]) -> Result<Option<Yaml>> {
+    let mut curr = match load_user_config()? {
+        Some(config) => config,
+        None => return Ok(None),
+    };
+    for k in keychain {
+        curr = match curr
+            .as_hash()
+            .and_then(|h| h.get(&Yaml::String(k.to_string())))
+        {
+            Some(val) => val.clone(),
+            None => return Ok(None),
+        };
+    }
+    Ok(Some(curr))
+}
+
+pub fn get_string(keychain: &[&str]) -> Result<Option<String>> {
+    Ok(get_setting(keychain)?.and_then(|v| v.as_str().map(|s| s.to_string())))
+}
+
+pub fn get_bool(keychain: &[&str]) -> Result<Option<bool>> {
+    Ok(get_setting(keychain)?.and_then(|v| v.as_bool()))
+}
+
+// paths in the config can start with `~`
+pub fn get_path(keychain: &[&str]) -> Result<Option<PathBuf>> {
+    match get_string(keychain)? {
+        Some(p) => Ok(Some(PathBuf::from(util::expand_home(&p)?))),
+        None => Ok(None),
+    }
+}
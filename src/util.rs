@@ -1,9 +1,13 @@
+use std::collections::HashSet;
 use std::fs;
 use std::path;
 use std::path::{Path, PathBuf};
 
 use anyhow::{bail, Context, Result};
+use chrono::format::{Item, StrftimeItems};
 use chrono::prelude::*;
+use chrono::{Months, TimeDelta};
+use sha2::{Digest, Sha256};
 use yaml_rust::{yaml, Yaml, YamlLoader};
 
 use crate::config::CONFIG;
@@ -43,29 +47,143 @@ pub fn stamp_local_dir() -> Result<()> {
     Ok(())
 }
 
+// how much of the due date was given, so it doesn't get shown any more
+//   precisely than that
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum DatePrecision {
+    Year,
+    Month,
+    Day,
+    Time,
+}
+
+pub struct DueDate {
+    // the actual deadline; if there's no time, it's the very end of the
+    //   year/month/day, in the local timezone
+    pub instant: DateTime<FixedOffset>,
+    pub precision: DatePrecision,
+}
+
+// `Z` works too, through the RFC 3339 parse
+const DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S%:z",
+    "%Y-%m-%dT%H:%M%:z",
+    "%Y-%m-%d %H:%M:%S%:z",
+    "%Y-%m-%d %H:%M%:z",
+];
+const LOCAL_DATETIME_FORMATS: &[&str] = &[
+    "%Y-%m-%dT%H:%M:%S",
+    "%Y-%m-%dT%H:%M",
+    "%Y-%m-%d %H:%M:%S",
+    "%Y-%m-%d %H:%M",
+];
+
+fn to_local_instant(ndt: &NaiveDateTime) -> DateTime<FixedOffset> {
+    match Local.from_local_datetime(ndt).earliest() {
+        Some(dt) => dt.fixed_offset(),
+        // skipped over by a DST change; close enough
+        None => Local.from_utc_datetime(ndt).fixed_offset(),
+    }
+}
+
+pub fn parse_due_date(date_string: &str) -> Result<DueDate> {
+    let s = date_string.trim();
+
+    if let Ok(dt) = DateTime::parse_from_rfc3339(s) {
+        return Ok(DueDate {
+            instant: dt,
+            precision: DatePrecision::Time,
+        });
+    }
+    for f in DATETIME_FORMATS {
+        if let Ok(dt) = DateTime::parse_from_str(s, f) {
+            return Ok(DueDate {
+                instant: dt,
+                precision: DatePrecision::Time,
+            });
+        }
+    }
+    for f in LOCAL_DATETIME_FORMATS {
+        if let Ok(ndt) = NaiveDateTime::parse_from_str(s, f) {
+            return Ok(DueDate {
+                instant: to_local_instant(&ndt),
+                precision: DatePrecision::Time,
+            });
+        }
+    }
+
+    let is_number =
+        |n: &str| !n.is_empty() && n.len() <= 4 && n.chars().all(|c| c.is_ascii_digit());
+    let (start, precision) = if let Ok(d) = NaiveDate::parse_from_str(s, "%Y-%m-%d") {
+        (Some(d), DatePrecision::Day)
+    } else if s
+        .split_once('-')
+        .is_some_and(|(y, m)| is_number(y) && is_number(m))
+    {
+        (
+            NaiveDate::parse_from_str(&format!("{}-01", s), "%Y-%m-%d").ok(),
+            DatePrecision::Month,
+        )
+    } else if is_number(s) {
+        (
+            s.parse()
+                .ok()
+                .and_then(|y| NaiveDate::from_ymd_opt(y, 1, 1)),
+            DatePrecision::Year,
+        )
+    } else {
+        (None, DatePrecision::Day)
+    };
+    let start = start.with_context(|| {
+        format!(
+            "Could not parse a date from \"{}\"; use YYYY, YYYY-MM, YYYY-MM-DD, or YYYY-MM-DDTHH:MM (optionally with a timezone like -05:00)",
+            s
+        )
+    })?;
+
+    let next = match precision {
+        DatePrecision::Year => start.checked_add_months(Months::new(12)),
+        DatePrecision::Month => start.checked_add_months(Months::new(1)),
+        _ => start.succ_opt(),
+    }
+    .with_context(|| format!("Date \"{}\" is out of range", s))?;
+    let end = next.and_time(NaiveTime::MIN) - TimeDelta::seconds(1);
+    Ok(DueDate {
+        instant: to_local_instant(&end),
+        precision,
+    })
+}
+
+pub fn get_due_date(meta: &metadata::PaperMeta) -> Result<Option<DueDate>> {
+    meta.data.date.as_deref().map(parse_due_date).transpose()
+}
+
 pub fn get_date_string(meta: &metadata::PaperMeta) -> Result<String> {
-    let date: DateTime<Local> = match meta.get_string(&["data", "date"]) {
+    let due = match get_due_date(meta)? {
         None => return Ok(String::new()),
-        Some(date_string) => {
-            let due = NaiveDate::parse_from_str(&date_string, "%Y-%m-%d")
-                .with_context(|| format!("Could not parse NaiveDate from {}", &date_string))?;
-            let due = due.and_time(NaiveTime::from_hms_opt(0, 1, 0).unwrap());
-            match Local.from_local_datetime(&due) {
-                chrono::LocalResult::Single(s) => s,
-                _ => Local::now(),
-            }
-        }
+        Some(d) => d,
     };
 
+    let mut format = match &meta.date_format {
+        Some(f) => f.clone(),
+        None => match due.precision {
+            DatePrecision::Year => "%Y".to_string(),
+            DatePrecision::Month => "%B %Y".to_string(),
+            DatePrecision::Day | DatePrecision::Time => "%B %-d, %Y".to_string(),
+        },
+    };
+    // chrono panics on bad specifiers once it actually formats
+    if StrftimeItems::new(&format).any(|i| i == Item::Error) {
+        bail!("Invalid `date_format` \"{}\"", format);
+    }
+
     // because one of my example documents has a due date of 33 AD, and what's
     //  the point of making your own system if you can't have a little Easter egg?
     //  :D
-    let mut year_str = date.year().to_string();
-    if year_str == "33" {
-        year_str = "A.U.C. 786".to_string();
+    if due.instant.year() == 33 {
+        format = format.replace("%Y", "A.U.C. 786");
     }
-    let out_string = format!("{}, {}", date.format("%B %-d"), year_str);
-    Ok(out_string)
+    Ok(due.instant.format(&format).to_string())
 }
 
 pub fn load_yml_file(path: &PathBuf) -> Result<Yaml> {
@@ -101,14 +219,13 @@ pub fn merge_yaml_hash(target: &mut yaml::Hash, new_hash: &yaml::Hash) {
     for (k, v) in new_hash {
         match target.get_mut(k) {
             Some(target_v) => match v {
-                Yaml::Hash(vh) => match target_v {
-                    Yaml::Hash(tvh) => {
+                Yaml::Hash(vh) => {
+                    if let Yaml::Hash(tvh) = target_v {
                         let mut new_v = tvh.clone();
                         merge_yaml_hash(&mut new_v, vh);
                         target[k] = Yaml::Hash(new_v);
                     }
-                    _ => {}
-                },
+                }
                 Yaml::Array(va) => match target_v {
                     Yaml::Array(tva) => {
                         let mut new_v = tva.clone();
@@ -137,6 +254,17 @@ pub fn merge_yaml_hash(target: &mut yaml::Hash, new_hash: &yaml::Hash) {
     }
 }
 
+pub fn expand_home(path_str: &str) -> Result<String> {
+    if let Some(rest) = path_str.strip_prefix('~') {
+        return Ok(format!(
+            "{}{}",
+            std::env::var("HOME").context("Could not get $HOME env var")?,
+            rest
+        ));
+    }
+    Ok(path_str.to_string())
+}
+
 pub fn find_meta(base: Option<&Path>) -> Result<path::PathBuf> {
     let options = vec![
         path::Path::new("paper_meta.yml"),
@@ -212,7 +340,7 @@ pub fn ensure_paper_dir() -> Result<()> {
 
 pub fn get_assignment() -> Result<String> {
     let meta = metadata::PaperMeta::new()?;
-    match meta.get_string(&["assignment"]) {
+    match meta.assignment {
         Some(s) => Ok(s),
         None => {
             let cwd = std::env::current_dir().context("Current path is invalid.")?;
@@ -224,3 +352,102 @@ pub fn get_assignment() -> Result<String> {
         }
     }
 }
+
+// checks a downloaded archive against the SHA-256 pinned for its file name;
+//   one that isn't pinned still gets used, but with a warning and its hash
+//   so it can be checked against the release page and added
+pub fn verify_download(archive_path: &Path, pinned: &[(&str, &str)]) -> Result<()> {
+    let filename = archive_path
+        .file_name()
+        .map(|f| f.to_string_lossy().to_string())
+        .context("Archive path has no file name")?;
+    let datums =
+        fs::read(archive_path).with_context(|| format!("Could not read {:?}", archive_path))?;
+    let actual = format!("{:x}", Sha256::digest(&datums));
+
+    let Some((_, expected)) = pinned.iter().find(|(name, _)| *name == filename) else {
+        eprintln!(
+            "[WARNING] No pinned checksum for {}, so it can't be verified; its SHA-256 is {}.",
+            filename, actual
+        );
+        return Ok(());
+    };
+    if actual != *expected {
+        bail!(
+            "Checksum mismatch for {}: expected {}, got {}",
+            filename,
+            expected,
+            actual
+        );
+    }
+    if CONFIG.get().verbose {
+        println!("Verified {} (SHA-256 {}).", filename, actual);
+    }
+    Ok(())
+}
+
+// the first executable on the PATH with this name, if there is one
+pub fn find_executable(name: &str) -> Option<PathBuf> {
+    let paths = std::env::var_os("PATH")?;
+    std::env::split_paths(&paths)
+        .map(|p| p.join(name))
+        .find(|p| p.is_file())
+}
+
+// lowercased, for matching up text that's been through different formatting
+pub fn get_words(text: &str) -> HashSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|w| !w.is_empty())
+        .map(|w| w.to_lowercase())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn end_of(y: i32, m: u32, d: u32) -> NaiveDateTime {
+        NaiveDate::from_ymd_opt(y, m, d)
+            .unwrap()
+            .and_hms_opt(23, 59, 59)
+            .unwrap()
+    }
+
+    #[test]
+    fn due_date_year() {
+        let due = parse_due_date("2024").unwrap();
+        assert_eq!(due.precision, DatePrecision::Year);
+        assert_eq!(due.instant.naive_local(), end_of(2024, 12, 31));
+    }
+
+    #[test]
+    fn due_date_month() {
+        let due = parse_due_date("2024-02").unwrap();
+        assert_eq!(due.precision, DatePrecision::Month);
+        assert_eq!(due.instant.naive_local(), end_of(2024, 2, 29));
+    }
+
+    #[test]
+    fn due_date_day() {
+        let due = parse_due_date("2024-12-31").unwrap();
+        assert_eq!(due.precision, DatePrecision::Day);
+        assert_eq!(due.instant.naive_local(), end_of(2024, 12, 31));
+    }
+
+    #[test]
+    fn due_date_with_timezone() {
+        let due = parse_due_date("2024-12-31T17:00-05:00").unwrap();
+        assert_eq!(due.precision, DatePrecision::Time);
+        assert_eq!(due.instant.to_rfc3339(), "2024-12-31T17:00:00-05:00");
+
+        let due = parse_due_date("2024-12-31T22:00:00Z").unwrap();
+        assert_eq!(due.instant.to_rfc3339(), "2024-12-31T22:00:00+00:00");
+    }
+
+    #[test]
+    fn due_date_nonsense() {
+        assert!(parse_due_date("next tuesday").is_err());
+        assert!(parse_due_date("2024-13").is_err());
+        assert!(parse_due_date("[DATE]").is_err());
+    }
+}
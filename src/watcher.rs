@@ -1,31 +1,447 @@
-use std::path::Path;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::mpsc::{Receiver, RecvTimeoutError};
+use std::time::Duration;
 
-use anyhow::Result;
+use anyhow::{bail, Context, Result};
 use chrono::prelude::*;
 use notify::{Config, RecommendedWatcher, RecursiveMode, Watcher};
+use serde_json::Value;
+use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, WriteColor};
 
 use crate::build;
 use crate::config::CONFIG;
 use crate::formats;
+use crate::metadata::PaperMeta;
+use crate::preview::PreviewServer;
+use crate::refs;
 use crate::util;
 use crate::wc;
 
-fn respond_to_event(
+// how often to check on a running build for new changes
+const BUILD_POLL_INTERVAL: Duration = Duration::from_millis(50);
+
+type EventReceiver = Receiver<notify::Result<notify::Event>>;
+
+struct WatchSettings {
     show_full: bool,
     should_build: bool,
-    output_format: &formats::OutputFormat,
+    output_format: formats::OutputFormat,
     of_specified: bool,
     docx_revision: i64,
+    debounce: Duration,
+    preview: Option<PreviewServer>,
+}
+
+// carried between updates so hooks can report how things have moved
+#[derive(Default)]
+struct WatchState {
+    last_word_count: Option<usize>,
+    // without building, the recorded state never moves on, so remember
+    //   which bibliography changes have already been mentioned
+    reported_bibliographies: HashMap<String, Value>,
+}
+
+// Single files (the meta file and bibliographies) are watched through their
+//   parent directories and picked out by name; lots of editors save by
+//   writing a new file and renaming it over the old one, which would leave a
+//   watch on the file itself pointing at nothing.
+struct WatchTargets {
+    recursive: Vec<PathBuf>,
+    files: Vec<PathBuf>,
+    file_dirs: Vec<PathBuf>,
+}
+
+impl WatchTargets {
+    fn new(watcher: &mut RecommendedWatcher, dirs: &[&Path]) -> Result<WatchTargets> {
+        let cwd = std::env::current_dir().context("Could not get current directory")?;
+        let mut recursive = vec![];
+        for dir in dirs {
+            watcher
+                .watch(dir, RecursiveMode::Recursive)
+                .with_context(|| format!("Could not watch {:?}", dir))?;
+            recursive.push(cwd.join(dir));
+        }
+        Ok(WatchTargets {
+            recursive,
+            files: vec![],
+            file_dirs: vec![],
+        })
+    }
+
+    fn is_relevant(&self, path: &Path) -> bool {
+        self.recursive.iter().any(|d| path.starts_with(d)) || self.files.iter().any(|f| f == path)
+    }
+
+    fn watch_files(&mut self, watcher: &mut RecommendedWatcher, files: Vec<PathBuf>) -> Result<()> {
+        let cwd = std::env::current_dir().context("Could not get current directory")?;
+        let files = files.iter().map(|f| cwd.join(f)).collect::<Vec<PathBuf>>();
+
+        // anything inside the recursively-watched directories is already covered
+        let mut dirs: Vec<PathBuf> = vec![];
+        for file in &files {
+            let dir = match file.parent() {
+                Some(d) if d.is_dir() => d.to_path_buf(),
+                _ => continue,
+            };
+            if !self.recursive.iter().any(|r| dir.starts_with(r)) && !dirs.contains(&dir) {
+                dirs.push(dir);
+            }
+        }
+
+        for old in &self.file_dirs {
+            if !dirs.contains(old) {
+                // may already be gone if the directory was deleted
+                let _ = watcher.unwatch(old);
+            }
+        }
+        for dir in &dirs {
+            if !self.file_dirs.contains(dir) {
+                watcher
+                    .watch(dir, RecursiveMode::NonRecursive)
+                    .with_context(|| format!("Could not watch directory {:?}", dir))?;
+            }
+        }
+
+        self.files = files;
+        self.file_dirs = dirs;
+        Ok(())
+    }
+}
+
+enum BuildOutcome {
+    Finished,
+    Interrupted(Vec<PathBuf>),
+}
+
+fn print_step_error(step: &str, e: &anyhow::Error) {
+    let mut err_format = ColorSpec::new();
+    err_format.set_fg(Some(Color::Red)).set_bold(true);
+    let mut stderr = StandardStream::stderr(ColorChoice::Auto);
+    let _ = stderr.set_color(&err_format);
+    eprint!("{} failed: ", step);
+    let _ = stderr.reset();
+    eprintln!("{:?}", e);
+    eprintln!("(Still watching...)\n");
+}
+
+// pulls out the paths from an event we care about; empty if it's not one
+fn relevant_paths(res: notify::Result<notify::Event>, targets: &WatchTargets) -> Vec<PathBuf> {
+    match res {
+        Err(e) => {
+            eprintln!("[WARNING] File watching error: {}", e);
+            vec![]
+        }
+        Ok(event) => {
+            let relevant = match event.kind {
+                notify::EventKind::Create(_) => true,
+                notify::EventKind::Modify(content) => {
+                    !matches!(content, notify::event::ModifyKind::Metadata(_))
+                }
+                notify::EventKind::Remove(_) => true,
+                _ => false,
+            };
+            if relevant {
+                event
+                    .paths
+                    .into_iter()
+                    .filter(|p| targets.is_relevant(p))
+                    .collect()
+            } else {
+                vec![]
+            }
+        }
+    }
+}
+
+fn add_paths(pending: &mut Vec<PathBuf>, paths: Vec<PathBuf>) {
+    for p in paths {
+        if !pending.contains(&p) {
+            pending.push(p);
+        }
+    }
+}
+
+// short, human-readable list of what changed
+fn describe_paths(paths: &[PathBuf]) -> Result<String> {
+    let cwd = std::env::current_dir().context("Could not get current directory")?;
+    let names = paths
+        .iter()
+        .map(|p| p.strip_prefix(&cwd).unwrap_or(p).display().to_string())
+        .collect::<Vec<String>>();
+    Ok(names.join(", "))
+}
+
+// (re-)registers the meta file and every bibliography listed in it, since
+//   the `sources` list can change while we're watching
+fn watch_meta_files(
+    watcher: &mut RecommendedWatcher,
+    targets: &mut WatchTargets,
+    meta_path: &Path,
 ) -> Result<()> {
+    let meta = PaperMeta::new()?;
+    let bibs = refs::get_bibliography_paths(&meta)?
+        .iter()
+        .map(PathBuf::from)
+        .collect::<Vec<PathBuf>>();
+
+    if CONFIG.get().verbose {
+        for bib in &bibs {
+            println!("Watching bibliography `{}`...", bib.display());
+        }
+    }
+
+    let mut files = vec![meta_path.to_path_buf()];
+    files.extend(bibs);
+    targets.watch_files(watcher, files)
+}
+
+fn kill_build(child: &mut process::Child) {
+    #[cfg(unix)]
+    // SAFETY: just sending a signal; a negative pid means the whole group
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.kill();
+    let _ = child.wait();
+}
+
+// Builds happen in a child process so they can be killed if more changes come
+//   in while they're running; there's no point finishing a build that's
+//   already stale.
+fn run_build(
+    output_format: Option<&formats::OutputFormat>,
+    docx_revision: i64,
+    targets: &WatchTargets,
+    rx: &EventReceiver,
+) -> Result<BuildOutcome> {
+    let exe = std::env::current_exe().context("Could not find path to current executable")?;
+    let mut args = vec!["build".to_string()];
+    if let Some(of) = output_format {
+        args.push("--output-format".to_string());
+        args.push(of.to_string());
+    }
+    // joined with `=` since the default is negative and would look like a flag
+    args.push(format!("--docx-revision={}", docx_revision));
+    if CONFIG.get().verbose {
+        args.push("--verbose".to_string());
+    }
+
+    let mut command = process::Command::new(&exe);
+    command.args(&args);
+    // the build has children of its own (pandoc, tectonic, ...), so it gets a
+    //   process group that can be killed all at once
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        command.process_group(0);
+    }
+    let mut child = command
+        .spawn()
+        .with_context(|| format!("Could not start build process {:?}", exe))?;
+
+    loop {
+        if let Some(status) = child
+            .try_wait()
+            .context("Could not check on build process")?
+        {
+            if !status.success() {
+                bail!("Build process exited with {}", status);
+            }
+            return Ok(BuildOutcome::Finished);
+        }
+        match rx.recv_timeout(BUILD_POLL_INTERVAL) {
+            Ok(res) => {
+                let paths = relevant_paths(res, targets);
+                if !paths.is_empty() {
+                    kill_build(&mut child);
+                    return Ok(BuildOutcome::Interrupted(paths));
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                kill_build(&mut child);
+                bail!("File watcher disconnected");
+            }
+        }
+    }
+}
+
+fn run_hook(hook: &str, meta: &PaperMeta, env: &[(&str, String)]) -> Result<()> {
+    let cmd = match meta.hooks.get(hook) {
+        Some(c) => c,
+        None => return Ok(()),
+    };
+    if CONFIG.get().verbose {
+        println!("Running `{}` hook:", hook);
+        println!("\t{}", cmd);
+    }
+
+    let mut command = if cfg!(windows) {
+        let mut c = process::Command::new("cmd");
+        c.args(["/C", cmd]);
+        c
+    } else {
+        let mut c = process::Command::new("sh");
+        c.args(["-c", cmd]);
+        c
+    };
+    let status = command
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .status()
+        .with_context(|| format!("Could not run `{}` hook", hook))?;
+    if !status.success() {
+        bail!("`{}` hook exited with {}", hook, status);
+    }
+
+    Ok(())
+}
+
+fn run_hooks(
+    settings: &WatchSettings,
+    state: &mut WatchState,
+    build_succeeded: Option<bool>,
+) -> Result<()> {
+    let meta = PaperMeta::new()?;
+    if meta.hooks.is_empty() {
+        // no sense paying for another word count
+        return Ok(());
+    }
+
+    let word_count: usize = wc::wc_data()?.iter().map(|d| d.2).sum();
+    let previous = state.last_word_count.replace(word_count);
+    let delta = match previous {
+        Some(prev) => word_count as i64 - prev as i64,
+        None => 0,
+    };
+    let of = build::resolve_output_format(&meta, &settings.output_format, settings.of_specified)?;
+    let output_path = build::get_output_file_path(&meta, &of)?;
+
+    let mut env = vec![
+        ("PAPER_OUTPUT_PATH", output_path.display().to_string()),
+        ("PAPER_WORD_COUNT", word_count.to_string()),
+        ("PAPER_WORD_COUNT_DELTA", delta.to_string()),
+    ];
+    let target_wc = meta.target_word_count.unwrap_or(-1);
+    if target_wc >= 0 {
+        env.push(("PAPER_TARGET_WORD_COUNT", target_wc.to_string()));
+    }
+
+    match build_succeeded {
+        Some(true) => run_hook("on_build_success", &meta, &env)?,
+        Some(false) => run_hook("on_build_failure", &meta, &env)?,
+        None => {}
+    }
+
+    // only when crossing the line, not every time after
+    if let Some(prev) = previous {
+        if target_wc >= 0 && (prev as i64) < target_wc && (word_count as i64) >= target_wc {
+            run_hook("on_target_reached", &meta, &env)?;
+        }
+    }
+
+    Ok(())
+}
+
+// returns any changes that arrived while it was working
+fn respond_to_changes(
+    changed: &[PathBuf],
+    settings: &WatchSettings,
+    state: &mut WatchState,
+    targets: &WatchTargets,
+    rx: &EventReceiver,
+) -> Vec<PathBuf> {
     let now = Local::now();
     let now_str = now.format("%Y-%m-%d %I:%M:%S %p").to_string();
-    println!("{}", now_str);
+    if changed.is_empty() {
+        println!("{}", now_str);
+    } else {
+        match describe_paths(changed) {
+            Ok(desc) => println!("{} ({} changed)", now_str, desc),
+            Err(_) => println!("{}", now_str),
+        }
+    }
+
+    let mut previewed = false;
+    let mut build_succeeded = None;
+    if settings.should_build {
+        let of = if settings.of_specified {
+            Some(&settings.output_format)
+        } else {
+            None
+        };
+        match run_build(of, settings.docx_revision, targets, rx) {
+            Ok(BuildOutcome::Finished) => {
+                build_succeeded = Some(true);
+                previewed = of == Some(&formats::OutputFormat::Html);
+            }
+            Ok(BuildOutcome::Interrupted(more)) => {
+                println!("More changes came in; restarting build...\n");
+                return more;
+            }
+            Err(e) => {
+                build_succeeded = Some(false);
+                print_step_error("Build", &e);
+            }
+        }
+    } else {
+        let changed_bibs =
+            PaperMeta::new().and_then(|meta| refs::get_changed_bibliographies(&meta));
+        match changed_bibs {
+            Ok(changed_bibs) => {
+                for changed in changed_bibs {
+                    let stamp = match refs::get_file_stamp(Path::new(&changed)) {
+                        Ok(stamp) => stamp,
+                        Err(e) => {
+                            print_step_error("Reading bibliography", &e);
+                            continue;
+                        }
+                    };
+                    if state.reported_bibliographies.get(&changed) == Some(&stamp) {
+                        continue;
+                    }
+                    println!(
+                        "Bibliography `{}` has changed since the last build.",
+                        changed
+                    );
+                    state.reported_bibliographies.insert(changed, stamp);
+                }
+            }
+            Err(e) => print_step_error("Reading metadata", &e),
+        }
+    }
+
+    if let Some(preview) = &settings.preview {
+        if !previewed {
+            match run_build(
+                Some(&formats::OutputFormat::Html),
+                settings.docx_revision,
+                targets,
+                rx,
+            ) {
+                Ok(BuildOutcome::Finished) => previewed = true,
+                Ok(BuildOutcome::Interrupted(more)) => {
+                    println!("More changes came in; restarting preview build...\n");
+                    return more;
+                }
+                Err(e) => print_step_error("Preview build", &e),
+            }
+        }
+        if previewed {
+            preview.notify_reload();
+        }
+    }
+
+    if let Err(e) = wc::wc(settings.show_full) {
+        print_step_error("Word count", &e);
+    }
 
-    if should_build {
-        build::build(output_format, of_specified, docx_revision)?;
+    if let Err(e) = run_hooks(settings, state, build_succeeded) {
+        print_step_error("Hook", &e);
     }
 
-    wc::wc(show_full)
+    vec![]
 }
 
 pub fn watch(
@@ -34,67 +450,77 @@ pub fn watch(
     output_format: formats::OutputFormat,
     of_specified: bool,
     docx_revision: i64,
+    debounce_ms: u64,
+    serve_port: Option<u16>,
 ) -> Result<()> {
     util::ensure_paper_dir()?;
 
+    let preview = match serve_port {
+        Some(port) => Some(PreviewServer::start(port)?),
+        None => None,
+    };
+
+    let settings = WatchSettings {
+        show_full,
+        should_build,
+        output_format,
+        of_specified,
+        docx_revision,
+        debounce: Duration::from_millis(debounce_ms),
+        preview,
+    };
+
     let (tx, rx) = std::sync::mpsc::channel();
 
     let mut watcher = RecommendedWatcher::new(tx, Config::default())?;
     let content_path = Path::new(&CONFIG.get().content_directory_name);
+    let resources_path = Path::new(".paper_resources");
     let config_path_buf = util::find_meta(None)?;
     let config_path = config_path_buf.as_path();
-    watcher.watch(content_path, RecursiveMode::Recursive)?;
-    watcher.watch(config_path, RecursiveMode::NonRecursive)?;
+    let mut targets = WatchTargets::new(&mut watcher, &[content_path, resources_path])?;
+    watch_meta_files(&mut watcher, &mut targets, config_path)?;
 
-    println!("Watching `{}` directory...", content_path.display());
+    println!(
+        "Watching `{}` and `{}` directories...",
+        content_path.display(),
+        resources_path.display()
+    );
+    if let Some(preview) = &settings.preview {
+        println!("Serving live preview at {}", preview.url());
+    }
     println!("(Press Ctrl-C to exit.)");
-    respond_to_event(
-        show_full,
-        should_build,
-        &output_format,
-        of_specified,
-        docx_revision,
-    )?;
+    let mut state = WatchState::default();
+    let mut pending = respond_to_changes(&[], &settings, &mut state, &targets, &rx);
 
-    for res in rx {
-        match res {
-            Err(e) => {
-                return Err(e.into());
-            }
-            Ok(event) => match event.kind {
-                notify::EventKind::Create(_) => {
-                    respond_to_event(
-                        show_full,
-                        should_build,
-                        &output_format,
-                        of_specified,
-                        docx_revision,
-                    )?;
-                }
-                notify::EventKind::Modify(content) => match content {
-                    notify::event::ModifyKind::Metadata(_) => {}
-                    _ => {
-                        respond_to_event(
-                            show_full,
-                            should_build,
-                            &output_format,
-                            of_specified,
-                            docx_revision,
-                        )?;
-                    }
-                },
-                notify::EventKind::Remove(_) => {
-                    respond_to_event(
-                        show_full,
-                        should_build,
-                        &output_format,
-                        of_specified,
-                        docx_revision,
-                    )?;
-                }
-                _ => {}
-            },
+    loop {
+        if pending.is_empty() {
+            match rx.recv() {
+                Ok(res) => add_paths(&mut pending, relevant_paths(res, &targets)),
+                Err(_) => break,
+            }
+            if pending.is_empty() {
+                continue;
+            }
+        }
+
+        // wait for things to settle down; editors often touch a file
+        //   several times for a single save
+        loop {
+            match rx.recv_timeout(settings.debounce) {
+                Ok(res) => add_paths(&mut pending, relevant_paths(res, &targets)),
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => return Ok(()),
+            }
         }
+
+        let changed = std::mem::take(&mut pending);
+        if changed.iter().any(|p| p.ends_with(config_path)) {
+            if let Err(e) = watch_meta_files(&mut watcher, &mut targets, config_path) {
+                print_step_error("Updating bibliography watches", &e);
+            }
+        }
+
+        pending = respond_to_changes(&changed, &settings, &mut state, &targets, &rx);
     }
 
     Ok(())
@@ -9,7 +9,7 @@ use crate::subprocess;
 use crate::util;
 
 fn count_words_in(s: &str) -> usize {
-    return s.split_whitespace().collect::<Vec<&str>>().len();
+    s.split_whitespace().collect::<Vec<&str>>().len()
 }
 
 // returns tuples of (filename, full_count, stripped_count)
@@ -118,7 +118,6 @@ pub fn wc_string(show_full: bool, delimit_final_row: bool) -> Result<String> {
     let mut header = vec!["File".to_string(), "Word Count".to_string()];
     if show_full {
         header.push("Stripped".to_string());
-    } else {
     }
 
     let mut table = vec![header];